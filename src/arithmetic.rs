@@ -0,0 +1,254 @@
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use crate::error::{Error, Result};
+use crate::float_image::{FImage, PixelFormat};
+
+// All the math here works on the color channels only, alpha keeps the value of the left operand.
+// Image operands need the same size, and the right one either has the same color channels as the
+// left one or is mono, in which case it applies to every color channel.
+
+impl FImage {
+    pub fn map_in_place<F: Fn(f32) -> f32>(&mut self, func: F) {
+        let channels = self.get_pixel_format().channel_count();
+        let color = channels.min(3);
+
+        for px in self.data_mut().chunks_exact_mut(channels) {
+            for v in px[..color].iter_mut() {
+                *v = func(*v);
+            }
+        }
+    }
+
+    pub fn map<F: Fn(f32) -> f32>(&self, func: F) -> FImage {
+        let mut out = self.clone();
+        out.map_in_place(func);
+
+        out
+    }
+
+    pub fn zip_map_in_place<F: Fn(f32, f32) -> f32>(&mut self, other: &FImage, func: F) {
+        self.try_zip_map_in_place(other, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_zip_map_in_place<F: Fn(f32, f32) -> f32>(&mut self, other: &FImage, func: F) -> Result<()> {
+        if other.width() != self.width() || other.height() != self.height() {
+            return Err(Error::DimensionMismatch { expected: (self.width(), self.height()), found: (other.width(), other.height()) });
+        }
+
+        let channels = self.get_pixel_format().channel_count();
+        let other_channels = other.get_pixel_format().channel_count();
+        let color = channels.min(3);
+
+        if other.get_pixel_format() != PixelFormat::Mono && other_channels.min(3) != color {
+            return Err(Error::UnsupportedPixelFormat { expected: self.get_pixel_format(), found: other.get_pixel_format() });
+        }
+
+        // a mono right hand side is read from offset 0 for every channel
+        let stride = if other_channels == 1 { 0 } else { 1 };
+        for (px, o) in self.data_mut().chunks_exact_mut(channels).zip(other.data().chunks_exact(other_channels)) {
+            for (c, v) in px[..color].iter_mut().enumerate() {
+                *v = func(*v, o[c * stride]);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn zip_map<F: Fn(f32, f32) -> f32>(&self, other: &FImage, func: F) -> FImage {
+        self.try_zip_map(other, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_zip_map<F: Fn(f32, f32) -> f32>(&self, other: &FImage, func: F) -> Result<FImage> {
+        let mut out = self.clone();
+        out.try_zip_map_in_place(other, func)?;
+
+        Ok(out)
+    }
+
+    pub fn abs(&self) -> FImage {
+        self.map(f32::abs)
+    }
+
+    pub fn sqrt(&self) -> FImage {
+        self.map(f32::sqrt)
+    }
+
+    pub fn pow(&self, exponent: f32) -> FImage {
+        self.map(|v| v.powf(exponent))
+    }
+
+    pub fn clamp(&self, min: f32, max: f32) -> FImage {
+        self.map(|v| v.clamp(min, max))
+    }
+
+    pub fn min(&self, other: &FImage) -> FImage {
+        self.zip_map(other, f32::min)
+    }
+
+    pub fn max(&self, other: &FImage) -> FImage {
+        self.zip_map(other, f32::max)
+    }
+
+    // self at t = 0, other at t = 1
+    pub fn lerp(&self, other: &FImage, t: f32) -> FImage {
+        self.zip_map(other, |a, b| a + (b - a) * t)
+    }
+}
+
+macro_rules! binary_op {
+    ($op:ident, $method:ident, $op_assign:ident, $method_assign:ident, $func:expr) => {
+        impl $op_assign<&FImage> for FImage {
+            fn $method_assign(&mut self, rhs: &FImage) {
+                self.zip_map_in_place(rhs, $func);
+            }
+        }
+
+        impl $op_assign<FImage> for FImage {
+            fn $method_assign(&mut self, rhs: FImage) {
+                self.zip_map_in_place(&rhs, $func);
+            }
+        }
+
+        impl $op_assign<f32> for FImage {
+            fn $method_assign(&mut self, rhs: f32) {
+                let func = $func;
+                self.map_in_place(|v| func(v, rhs));
+            }
+        }
+
+        impl $op<&FImage> for &FImage {
+            type Output = FImage;
+
+            fn $method(self, rhs: &FImage) -> FImage {
+                self.zip_map(rhs, $func)
+            }
+        }
+
+        impl $op<FImage> for &FImage {
+            type Output = FImage;
+
+            fn $method(self, rhs: FImage) -> FImage {
+                self.zip_map(&rhs, $func)
+            }
+        }
+
+        impl $op<&FImage> for FImage {
+            type Output = FImage;
+
+            fn $method(mut self, rhs: &FImage) -> FImage {
+                self.zip_map_in_place(rhs, $func);
+                self
+            }
+        }
+
+        impl $op<FImage> for FImage {
+            type Output = FImage;
+
+            fn $method(mut self, rhs: FImage) -> FImage {
+                self.zip_map_in_place(&rhs, $func);
+                self
+            }
+        }
+
+        impl $op<f32> for &FImage {
+            type Output = FImage;
+
+            fn $method(self, rhs: f32) -> FImage {
+                let func = $func;
+                self.map(|v| func(v, rhs))
+            }
+        }
+
+        impl $op<f32> for FImage {
+            type Output = FImage;
+
+            fn $method(mut self, rhs: f32) -> FImage {
+                let func = $func;
+                self.map_in_place(|v| func(v, rhs));
+                self
+            }
+        }
+    };
+}
+
+binary_op!(Add, add, AddAssign, add_assign, |a: f32, b: f32| a + b);
+binary_op!(Sub, sub, SubAssign, sub_assign, |a: f32, b: f32| a - b);
+binary_op!(Mul, mul, MulAssign, mul_assign, |a: f32, b: f32| a * b);
+binary_op!(Div, div, DivAssign, div_assign, |a: f32, b: f32| a / b);
+
+impl Neg for &FImage {
+    type Output = FImage;
+
+    fn neg(self) -> FImage {
+        self.map(|v| -v)
+    }
+}
+
+impl Neg for FImage {
+    type Output = FImage;
+
+    fn neg(mut self) -> FImage {
+        self.map_in_place(|v| -v);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+
+    fn filled(format: PixelFormat, values: &[f32]) -> FImage {
+        let mut img = FImage::new(2, 1, format);
+        img.data_mut().chunks_exact_mut(values.len()).for_each(|p| p.copy_from_slice(values));
+
+        img
+    }
+
+    #[test]
+    fn operators_skip_alpha() {
+        let a = filled(PixelFormat::RGBA, &[1.0, 2.0, 3.0, 0.5]);
+        let b = filled(PixelFormat::RGBA, &[4.0, 4.0, 4.0, 0.25]);
+
+        assert_eq!((&a + &b).get_pixel(0, 0).slice(), &[5.0, 6.0, 7.0, 0.5]);
+        assert_eq!((&a - &b).get_pixel(1, 0).slice(), &[-3.0, -2.0, -1.0, 0.5]);
+        assert_eq!((&a * 2.0).get_pixel(0, 0).slice(), &[2.0, 4.0, 6.0, 0.5]);
+        assert_eq!((a.clone() / b.clone()).get_pixel(0, 0).slice(), &[0.25, 0.5, 0.75, 0.5]);
+        assert_eq!((-&a).get_pixel(0, 0).slice(), &[-1.0, -2.0, -3.0, 0.5]);
+
+        let mut c = a.clone();
+        c += &b;
+        c -= 1.0;
+        c *= b;
+        assert_eq!(c.get_pixel(1, 0).slice(), &[16.0, 20.0, 24.0, 0.5]);
+    }
+
+    #[test]
+    fn mono_operands_broadcast() {
+        let a = filled(PixelFormat::RGB, &[1.0, 2.0, 3.0]);
+        let m = filled(PixelFormat::Mono, &[2.0]);
+
+        assert_eq!((&a * &m).data(), &[2.0, 4.0, 6.0, 2.0, 4.0, 6.0]);
+        assert_eq!((&filled(PixelFormat::RGBA, &[1.0, 2.0, 3.0, 0.5]) + &a).get_pixel(0, 0).slice(), &[2.0, 4.0, 6.0, 0.5]);
+
+        assert!(matches!(m.try_zip_map(&a, f32::max), Err(Error::UnsupportedPixelFormat { .. })));
+        assert!(matches!(a.try_zip_map(&FImage::new(1, 1, PixelFormat::Mono), f32::max), Err(Error::DimensionMismatch { .. })));
+    }
+
+    #[test]
+    fn elementwise_math() {
+        let mut a = FImage::new(2, 1, PixelFormat::RGBA);
+        a.set_pixel(0, 0, Pixel::rgba(-4.0, 9.0, 0.25, -1.0));
+        a.set_pixel(1, 0, Pixel::rgba(2.0, 0.0, 1.0, 2.0));
+
+        assert_eq!(a.abs().get_pixel(0, 0).slice(), &[4.0, 9.0, 0.25, -1.0]);
+        assert_eq!(a.abs().sqrt().get_pixel(0, 0).slice(), &[2.0, 3.0, 0.5, -1.0]);
+        assert_eq!(a.pow(2.0).get_pixel(1, 0).slice(), &[4.0, 0.0, 1.0, 2.0]);
+        assert_eq!(a.clamp(0.0, 1.0).get_pixel(0, 0).slice(), &[0.0, 1.0, 0.25, -1.0]);
+
+        let b = a.map(|v| v + 1.0);
+        assert_eq!(a.min(&b).data(), a.data());
+        assert_eq!(a.max(&b).get_pixel(1, 0).slice(), &[3.0, 1.0, 2.0, 2.0]);
+        assert_eq!(a.lerp(&b, 0.5).get_pixel(1, 0).slice(), &[2.5, 0.5, 1.5, 2.0]);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::compositing::{premultiply, unpremultiply};
+use crate::float_image::{FImage, Pixel, PixelFormat};
+
+// separable and non-separable blend modes as defined by the W3C compositing spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    SoftLight,
+    HardLight,
+    Darken,
+    Lighten,
+    Difference,
+    Exclusion,
+    ColorDodge,
+    ColorBurn,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity
+}
+
+fn hard_light(cb: f32, cs: f32) -> f32 {
+    if cs <= 0.5 {
+        cb * 2.0 * cs
+    } else {
+        let s = 2.0 * cs - 1.0;
+        cb + s - cb * s
+    }
+}
+
+fn soft_light(cb: f32, cs: f32) -> f32 {
+    if cs <= 0.5 {
+        cb - (1.0 - 2.0 * cs) * cb * (1.0 - cb)
+    } else {
+        let d = if cb <= 0.25 { ((16.0 * cb - 12.0) * cb + 4.0) * cb } else { cb.sqrt() };
+        cb + (2.0 * cs - 1.0) * (d - cb)
+    }
+}
+
+fn color_dodge(cb: f32, cs: f32) -> f32 {
+    if cb == 0.0 {
+        0.0
+    } else if cs >= 1.0 {
+        1.0
+    } else {
+        (cb / (1.0 - cs)).min(1.0)
+    }
+}
+
+fn color_burn(cb: f32, cs: f32) -> f32 {
+    if cb == 1.0 {
+        1.0
+    } else if cs <= 0.0 {
+        0.0
+    } else {
+        1.0 - ((1.0 - cb) / cs).min(1.0)
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn clip_color(c: [f32; 3]) -> [f32; 3] {
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+
+    let mut out = c;
+    for v in out.iter_mut() {
+        if n < 0.0 {
+            *v = l + (*v - l) * l / (l - n);
+        }
+        if x > 1.0 {
+            *v = l + (*v - l) * (1.0 - l) / (x - l);
+        }
+    }
+
+    out
+}
+
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+
+    clip_color([c[0] + d, c[1] + d, c[2] + d])
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let mut idx = [0, 1, 2];
+    idx.sort_by(|a, b| c[*a].total_cmp(&c[*b]));
+    let (min, mid, max) = (idx[0], idx[1], idx[2]);
+
+    let mut out = [0.0; 3];
+    if c[max] > c[min] {
+        out[mid] = (c[mid] - c[min]) * s / (c[max] - c[min]);
+        out[max] = s;
+    }
+
+    out
+}
+
+impl BlendMode {
+    // blends source color cs onto backdrop color cb, both straight and without alpha
+    pub fn blend(&self, cb: [f32; 3], cs: [f32; 3]) -> [f32; 3] {
+        let separable = |f: fn(f32, f32) -> f32| [f(cb[0], cs[0]), f(cb[1], cs[1]), f(cb[2], cs[2])];
+
+        match self {
+            BlendMode::Normal => cs,
+            BlendMode::Multiply => separable(|b, s| b * s),
+            BlendMode::Screen => separable(|b, s| b + s - b * s),
+            BlendMode::Overlay => separable(|b, s| hard_light(s, b)),
+            BlendMode::SoftLight => separable(soft_light),
+            BlendMode::HardLight => separable(hard_light),
+            BlendMode::Darken => separable(f32::min),
+            BlendMode::Lighten => separable(f32::max),
+            BlendMode::Difference => separable(|b, s| (b - s).abs()),
+            BlendMode::Exclusion => separable(|b, s| b + s - 2.0 * b * s),
+            BlendMode::ColorDodge => separable(color_dodge),
+            BlendMode::ColorBurn => separable(color_burn),
+            BlendMode::Hue => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+            BlendMode::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+            BlendMode::Color => set_lum(cs, lum(cb)),
+            BlendMode::Luminosity => set_lum(cb, lum(cs))
+        }
+    }
+}
+
+impl Pixel<'_> {
+    // Blends this pixel onto backdrop and composites the result over it, with the source alpha scaled by opacity.
+    // The result has backdrop's format, backdrops without an alpha channel count as opaque and mono ones get its luma.
+    pub fn blend(&self, backdrop: &Pixel, mode: BlendMode, opacity: f32) -> Pixel<'static> {
+        let cb = [backdrop.r(), backdrop.g(), backdrop.b()];
+        let cs = [self.r(), self.g(), self.b()];
+        let ab = backdrop.a();
+        let a_s = self.a() * opacity;
+
+        // where the backdrop is transparent the source shows through unblended
+        let mixed = mode.blend(cb, cs);
+        let src = [0, 1, 2].map(|i| (1.0 - ab) * cs[i] + ab * mixed[i]);
+
+        let s = premultiply([src[0], src[1], src[2], a_s]);
+        let b = premultiply([cb[0], cb[1], cb[2], ab]);
+        let out = [0, 1, 2, 3].map(|i| s[i] + (1.0 - a_s) * b[i]);
+
+        match backdrop.format() {
+            PixelFormat::RGBA => {
+                let c = unpremultiply(out);
+                Pixel::rgba(c[0], c[1], c[2], c[3])
+            },
+            PixelFormat::RGB => Pixel::rgb(out[0], out[1], out[2]),
+            PixelFormat::Mono => Pixel::mono(Pixel::rgb(out[0], out[1], out[2]).luma())
+        }
+    }
+}
+
+impl FImage {
+    pub fn set_pixel_blend_mode(&mut self, x: i32, y: i32, pixel: Pixel, mode: BlendMode, opacity: f32) {
+        if self.write_coords(x, y).is_none() {
+            return;
+        }
+
+        let new_pixel = pixel.blend(&self.get_pixel(x, y), mode, opacity);
+
+        self.set_pixel(x, y, new_pixel);
+    }
+}
+
+// blends top onto bottom, the result takes bottom's format, border mode and color space
+pub fn blend_images(top: &FImage, bottom: &FImage, mode: BlendMode, opacity: f32) -> FImage {
+    let mut out = bottom.clone();
+
+    for x in 0..bottom.width() as i32 {
+        for y in 0..bottom.height() as i32 {
+            out.set_pixel_blend_mode(x, y, top.get_pixel(x, y), mode, opacity);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blend_images, BlendMode};
+    use crate::circle_drawer::fill_circle_blend_mode;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+    use crate::image_filter::fn_filter;
+
+    const MODES: [BlendMode; 16] = [BlendMode::Normal, BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay, BlendMode::SoftLight,
+                                    BlendMode::HardLight, BlendMode::Darken, BlendMode::Lighten, BlendMode::Difference, BlendMode::Exclusion,
+                                    BlendMode::ColorDodge, BlendMode::ColorBurn, BlendMode::Hue, BlendMode::Saturation, BlendMode::Color,
+                                    BlendMode::Luminosity];
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-5)
+    }
+
+    #[test]
+    fn separable_modes() {
+        let (cb, cs) = ([0.2, 0.5, 0.8], [0.5, 0.25, 1.0]);
+
+        assert!(close(&BlendMode::Multiply.blend(cb, cs), &[0.1, 0.125, 0.8]));
+        assert!(close(&BlendMode::Screen.blend(cb, cs), &[0.6, 0.625, 1.0]));
+        assert!(close(&BlendMode::Darken.blend(cb, cs), &[0.2, 0.25, 0.8]));
+        assert!(close(&BlendMode::Lighten.blend(cb, cs), &[0.5, 0.5, 1.0]));
+        assert!(close(&BlendMode::Difference.blend(cb, cs), &[0.3, 0.25, 0.2]));
+        assert!(close(&BlendMode::Multiply.blend(cb, [1.0; 3]), &cb));
+        assert!(close(&BlendMode::Screen.blend(cb, [0.0; 3]), &cb));
+    }
+
+    #[test]
+    fn luminosity_and_color_swap_roles() {
+        let (cb, cs) = ([0.2, 0.5, 0.8], [0.9, 0.3, 0.1]);
+        let lum = |c: [f32; 3]| 0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2];
+
+        assert!((lum(BlendMode::Luminosity.blend(cb, cs)) - lum(cs)).abs() < 1e-5);
+        assert!((lum(BlendMode::Color.blend(cb, cs)) - lum(cb)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn opacity_and_alpha() {
+        let top = Pixel::rgba(1.0, 1.0, 1.0, 1.0);
+        let bottom = Pixel::rgb(0.2, 0.4, 0.6);
+
+        for mode in MODES {
+            assert!(close(top.blend(&bottom, mode, 0.0).slice(), bottom.slice()), "{:?}", mode);
+        }
+        assert!(close(Pixel::rgba(0.5, 0.5, 0.5, 1.0).blend(&bottom, BlendMode::Multiply, 0.5).slice(), &[0.15, 0.3, 0.45]));
+        // a transparent backdrop shows the source unblended
+        assert!(close(Pixel::rgba(0.5, 0.5, 0.5, 1.0).blend(&Pixel::rgba(0.2, 0.4, 0.6, 0.0), BlendMode::Multiply, 1.0).slice(), &[0.5, 0.5, 0.5, 1.0]));
+        assert!(close(top.blend(&Pixel::mono(0.5), BlendMode::Multiply, 1.0).slice(), &[0.5]));
+        assert!(close(Pixel::rgba(0.0, 1.0, 0.0, 1.0).blend(&Pixel::mono(0.5), BlendMode::Normal, 1.0).slice(), &[0.7152]));
+    }
+
+    #[test]
+    fn images_and_circles() {
+        let blank = FImage::new(3, 3, PixelFormat::RGB);
+        let top = fn_filter(&blank, |_, _, _| Pixel::rgb(0.5, 0.5, 0.5));
+        let mut bottom = fn_filter(&blank, |_, _, _| Pixel::rgb(0.4, 0.4, 0.4));
+        let blended = blend_images(&top, &bottom, BlendMode::Multiply, 1.0);
+        for (x, y) in [(0, 0), (2, 0), (1, 1), (0, 2), (2, 2)] {
+            assert!(close(blended.get_pixel(x, y).slice(), &[0.2; 3]));
+        }
+
+        fill_circle_blend_mode(1, 1, 0, Pixel::rgb(0.5, 0.5, 0.5), BlendMode::Screen, false, &mut bottom);
+        assert!(close(bottom.get_pixel(1, 1).slice(), &[0.7; 3]));
+        assert!(close(bottom.get_pixel(0, 0).slice(), &[0.4; 3]));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::error::{Error, Result};
+use crate::float_image::{FImage, PixelFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumaWeights {
+    Rec601,
+    Rec709,
+    // plain mean of r, g and b
+    Average
+}
+
+impl LumaWeights {
+    pub fn weights(&self) -> [f32; 3] {
+        match self {
+            LumaWeights::Rec601 => [0.299, 0.587, 0.114],
+            LumaWeights::Rec709 => [0.2126, 0.7152, 0.0722],
+            LumaWeights::Average => [1.0 / 3.0; 3]
+        }
+    }
+
+    pub fn luma(&self, r: f32, g: f32, b: f32) -> f32 {
+        let w = self.weights();
+
+        w[0] * r + w[1] * g + w[2] * b
+    }
+}
+
+// how to_format fills in or reduces channels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatConversion {
+    // used when going from color to mono
+    pub weights: LumaWeights,
+    // alpha of images that didn't have any
+    pub alpha: f32
+}
+
+impl Default for FormatConversion {
+    fn default() -> FormatConversion {
+        FormatConversion { weights: LumaWeights::Rec709, alpha: 1.0 }
+    }
+}
+
+impl FormatConversion {
+    pub fn new(weights: LumaWeights, alpha: f32) -> FormatConversion {
+        FormatConversion { weights, alpha }
+    }
+}
+
+impl FImage {
+    // mono copy of a single channel
+    pub fn channel(&self, channel: usize) -> FImage {
+        self.try_channel(channel).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_channel(&self, channel: usize) -> Result<FImage> {
+        self.try_swizzle(&[channel])
+    }
+
+    pub fn split_channels(&self) -> Vec<FImage> {
+        (0..self.get_pixel_format().channel_count()).map(|c| self.channel(c)).collect()
+    }
+
+    // Builds a Mono, RGB or RGBA image out of 1, 3 or 4 mono images of the same size.
+    // Border mode and color space come from the first image.
+    pub fn merge_channels(channels: &[&FImage]) -> FImage {
+        FImage::try_merge_channels(channels).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_merge_channels(channels: &[&FImage]) -> Result<FImage> {
+        let format = PixelFormat::from_channel_count(channels.len()).ok_or(Error::InvalidChannelCount(channels.len()))?;
+        let first = channels[0];
+
+        for img in channels {
+            if img.get_pixel_format() != PixelFormat::Mono {
+                return Err(Error::UnsupportedPixelFormat { expected: PixelFormat::Mono, found: img.get_pixel_format() });
+            }
+            if img.width() != first.width() || img.height() != first.height() {
+                return Err(Error::DimensionMismatch { expected: (first.width(), first.height()), found: (img.width(), img.height()) });
+            }
+        }
+
+        let mut out = first.new_like(format);
+        for (i, px) in out.data_mut().chunks_exact_mut(channels.len()).enumerate() {
+            for (v, img) in px.iter_mut().zip(channels) {
+                *v = img.data()[i];
+            }
+        }
+
+        Ok(out)
+    }
+
+    // Output channel i is a copy of channel order[i], so [2, 1, 0] turns RGB into BGR and [0, 0, 0, 3]
+    // spreads red over an RGBA image. The length of order (1, 3 or 4) picks the output format.
+    pub fn swizzle(&self, order: &[usize]) -> FImage {
+        self.try_swizzle(order).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_swizzle(&self, order: &[usize]) -> Result<FImage> {
+        let format = PixelFormat::from_channel_count(order.len()).ok_or(Error::InvalidChannelCount(order.len()))?;
+        let channels = self.get_pixel_format().channel_count();
+
+        if let Some(&channel) = order.iter().find(|&&c| c >= channels) {
+            return Err(Error::InvalidChannel { channel, format: self.get_pixel_format() });
+        }
+
+        let mut out = self.new_like(format);
+        for (dst, src) in out.data_mut().chunks_exact_mut(order.len()).zip(self.data().chunks_exact(channels)) {
+            for (v, &c) in dst.iter_mut().zip(order) {
+                *v = src[c];
+            }
+        }
+
+        Ok(out)
+    }
+
+    // luma for color to mono, broadcast for mono to color, opaque alpha where there was none
+    pub fn to_format(&self, format: PixelFormat) -> FImage {
+        self.to_format_with(format, &FormatConversion::default())
+    }
+
+    pub fn to_format_with(&self, format: PixelFormat, conversion: &FormatConversion) -> FImage {
+        if format == self.get_pixel_format() {
+            return self.clone();
+        }
+
+        let mut out = self.new_like(format);
+        let channels = format.channel_count();
+
+        for (dst, src) in out.data_mut().chunks_exact_mut(channels).zip(self.pixels()) {
+            match format {
+                PixelFormat::Mono => dst[0] = conversion.weights.luma(src.r(), src.g(), src.b()),
+                PixelFormat::RGB => dst.copy_from_slice(&[src.r(), src.g(), src.b()]),
+                PixelFormat::RGBA => {
+                    let a = if src.format() == PixelFormat::RGBA { src.a() } else { conversion.alpha };
+                    dst.copy_from_slice(&[src.r(), src.g(), src.b(), a]);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FormatConversion, LumaWeights};
+    use crate::color_space::ColorSpace;
+    use crate::error::Error;
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+
+    fn rgba() -> FImage {
+        let mut img = FImage::new(3, 2, PixelFormat::RGBA);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = i as f32 / 24.0;
+        }
+        img.set_border_mode(BorderMode::Clamp);
+        img.set_color_space(ColorSpace::LinearSrgb);
+
+        img
+    }
+
+    #[test]
+    fn split_and_merge_round_trip() {
+        let img = rgba();
+        let parts = img.split_channels();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[2].data(), &[2.0 / 24.0, 6.0 / 24.0, 10.0 / 24.0, 14.0 / 24.0, 18.0 / 24.0, 22.0 / 24.0]);
+
+        let merged = FImage::merge_channels(&parts.iter().collect::<Vec<_>>());
+        assert_eq!(merged.data(), img.data());
+        assert!(matches!(merged.border_mode(), BorderMode::Clamp));
+        assert_eq!(merged.color_space(), ColorSpace::LinearSrgb);
+
+        assert!(matches!(FImage::try_merge_channels(&[&parts[0], &parts[1]]), Err(Error::InvalidChannelCount(2))));
+        assert!(matches!(FImage::try_merge_channels(&[&img]), Err(Error::UnsupportedPixelFormat { .. })));
+        let small = FImage::new(1, 1, PixelFormat::Mono);
+        assert!(matches!(FImage::try_merge_channels(&[&parts[0], &parts[1], &small]), Err(Error::DimensionMismatch { .. })));
+    }
+
+    #[test]
+    fn swizzles() {
+        let img = rgba();
+        let bgr = img.swizzle(&[2, 1, 0]);
+        assert_eq!(bgr.get_pixel_format(), PixelFormat::RGB);
+        assert_eq!(bgr.get_pixel(1, 0).slice(), &[6.0 / 24.0, 5.0 / 24.0, 4.0 / 24.0]);
+        assert_eq!(img.swizzle(&[3, 3, 3, 3]).get_pixel(0, 1).slice(), &[15.0 / 24.0; 4]);
+
+        assert!(matches!(img.try_channel(4), Err(Error::InvalidChannel { channel: 4, .. })));
+        assert!(matches!(img.try_swizzle(&[0, 1]), Err(Error::InvalidChannelCount(2))));
+    }
+
+    #[test]
+    fn format_conversions() {
+        let mut img = FImage::new(1, 1, PixelFormat::RGB);
+        img.set_pixel(0, 0, Pixel::rgb(1.0, 0.5, 0.0));
+
+        assert!((img.to_format(PixelFormat::Mono).data()[0] - (0.2126 + 0.5 * 0.7152)).abs() < 1e-6);
+        let average = FormatConversion::new(LumaWeights::Average, 1.0);
+        assert!((img.to_format_with(PixelFormat::Mono, &average).data()[0] - 0.5).abs() < 1e-6);
+        assert_eq!(img.to_format_with(PixelFormat::RGBA, &FormatConversion::new(LumaWeights::Rec601, 0.25)).data(), &[1.0, 0.5, 0.0, 0.25]);
+
+        let mono = img.to_format(PixelFormat::Mono);
+        assert_eq!(mono.to_format(PixelFormat::RGBA).data(), &[mono.data()[0], mono.data()[0], mono.data()[0], 1.0]);
+        assert_eq!(rgba().to_format(PixelFormat::RGB).data()[3..6], [4.0 / 24.0, 5.0 / 24.0, 6.0 / 24.0]);
+    }
+}
@@ -1,6 +1,8 @@
-use crate::float_image::{Pixel, FImage};
+use crate::blend_mode::BlendMode;
+use crate::float_image::Pixel;
+use crate::image_view::{GenericFImage, GenericFImageMut};
 
-fn draw_points(x: i32, y: i32, i: i32, j: i32, color: Pixel, img: &mut FImage) {
+fn draw_points<I: GenericFImageMut + ?Sized>(x: i32, y: i32, i: i32, j: i32, color: Pixel, wrap: bool, img: &mut I) {
         // '''Draws 8 points, one on each octant.'''
         let mut coords = Vec::new();
         for k in 0..4i32 {
@@ -15,11 +17,11 @@ fn draw_points(x: i32, y: i32, i: i32, j: i32, color: Pixel, img: &mut FImage) {
         }
 
         for (i_, j_) in coords {
-            img.set_pixel_blended(x + i_, y + j_, color.to_owned());
+            set_pixel_blended(x + i_, y + j_, color, wrap, img);
         }
 }
 
-pub fn draw_circle(x: i32, y: i32, radius: i32, color: Pixel, wrap: bool, img: &mut FImage) {
+pub fn draw_circle<I: GenericFImageMut + ?Sized>(x: i32, y: i32, radius: i32, color: Pixel, wrap: bool, img: &mut I) {
     let mut i = radius;
     let mut j = 0;
     let mut t = 0;
@@ -38,49 +40,86 @@ pub fn draw_circle(x: i32, y: i32, radius: i32, color: Pixel, wrap: bool, img: &
         let p1 = Pixel::rgba(color.r(), color.g(), color.b(), d_ as f32 / 255.0);
         let p2 = Pixel::rgba(color.r(), color.g(), color.b(), d as f32 / 255.0);
 
-        draw_points(x, y, i, j, p1, img);
-        draw_points(x, y, i - 1, j, p2, img);
+        draw_points(x, y, i, j, p1, wrap, img);
+        draw_points(x, y, i - 1, j, p2, wrap, img);
 
         t = d;
     }
 
     // Fill in gaps on axes
-    img.set_pixel(x + radius, y, color.clone());
-    img.set_pixel(x - radius, y, color.clone());
-    img.set_pixel(x, y + radius, color.clone());
-    img.set_pixel(x, y - radius, color.clone());  
+    set_pixel(x + radius, y, color, wrap, img);
+    set_pixel(x - radius, y, color, wrap, img);
+    set_pixel(x, y + radius, color, wrap, img);
+    set_pixel(x, y - radius, color, wrap, img);  
 }
 
-fn set_pixel(x: i32, y: i32, color: Pixel, wrap: bool, img: &mut FImage) {
-    if wrap || (x >= 0 && x < img.width() as i32 && y >= 0 && y < img.height() as i32) {
+// wrap explicitly so the result doesn't depend on the image's border mode
+fn target_coords<I: GenericFImage + ?Sized>(x: i32, y: i32, wrap: bool, img: &I) -> Option<(i32, i32)> {
+    if wrap {
+        Some((x.rem_euclid(img.width() as i32), y.rem_euclid(img.height() as i32)))
+    } else if img.in_bounds(x, y) {
+        Some((x, y))
+    } else {
+        None
+    }
+}
+
+fn set_pixel<I: GenericFImageMut + ?Sized>(x: i32, y: i32, color: Pixel, wrap: bool, img: &mut I) {
+    if let Some((x, y)) = target_coords(x, y, wrap, img) {
         img.set_pixel(x, y, color);
     }
 }
 
-fn set_pixel_blended(x: i32, y: i32, color: Pixel, wrap: bool, img: &mut FImage) {
-    if wrap || (x >= 0 && x < img.width() as i32 && y >= 0 && y < img.height() as i32) {
+fn set_pixel_blended<I: GenericFImageMut + ?Sized>(x: i32, y: i32, color: Pixel, wrap: bool, img: &mut I) {
+    if let Some((x, y)) = target_coords(x, y, wrap, img) {
         img.set_pixel_blended(x, y, color);
     }
 }
 
-pub fn fill_circle(x: i32, y: i32, radius: i32, color: Pixel, wrap: bool, img: &mut FImage) {
+pub fn fill_circle<I: GenericFImageMut + ?Sized>(x: i32, y: i32, radius: i32, color: Pixel, wrap: bool, img: &mut I) {
     for i in 0..=radius {
         for j in 0..=radius {
             let d = ((i*i + j*j) as f32).sqrt();
             let diff = d - radius as f32;
             if diff < 0.0 {
-                set_pixel(x + i, y + j, color.clone(), wrap, img);
-                set_pixel(x - i, y + j, color.clone(), wrap, img);
-                set_pixel(x + i, y - j, color.clone(), wrap, img);
-                set_pixel(x - i, y - j, color.clone(), wrap, img);
+                set_pixel(x + i, y + j, color, wrap, img);
+                set_pixel(x - i, y + j, color, wrap, img);
+                set_pixel(x + i, y - j, color, wrap, img);
+                set_pixel(x - i, y - j, color, wrap, img);
             } else if diff < 1.0 {
                 let newcolor = Pixel::rgba(color.r(), color.g(), color.b(), 1.0 - diff);
 
-                set_pixel_blended(x + i, y + j, newcolor.clone(), wrap, img);
-                set_pixel_blended(x - i, y + j, newcolor.clone(), wrap, img);
-                set_pixel_blended(x + i, y - j, newcolor.clone(), wrap, img);
+                set_pixel_blended(x + i, y + j, newcolor, wrap, img);
+                set_pixel_blended(x - i, y + j, newcolor, wrap, img);
+                set_pixel_blended(x + i, y - j, newcolor, wrap, img);
                 set_pixel_blended(x - i, y - j, newcolor, wrap, img);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// like fill_circle, but every pixel is blended onto the image with the given mode, the color's alpha sets the opacity
+pub fn fill_circle_blend_mode<I: GenericFImageMut + ?Sized>(x: i32, y: i32, radius: i32, color: Pixel, mode: BlendMode, wrap: bool, img: &mut I) {
+    for i in 0..=radius {
+        for j in 0..=radius {
+            let d = ((i*i + j*j) as f32).sqrt();
+            let diff = d - radius as f32;
+            if diff >= 1.0 {
+                continue;
+            }
+
+            let coverage = if diff < 0.0 { 1.0 } else { 1.0 - diff };
+
+            for (sx, sy) in [(1, 1), (-1, 1), (1, -1), (-1, -1)] {
+                // pixels on the axes would otherwise be blended twice
+                if (sx < 0 && i == 0) || (sy < 0 && j == 0) {
+                    continue;
+                }
+
+                if let Some((px, py)) = target_coords(x + sx * i, y + sy * j, wrap, img) {
+                    img.set_pixel_blend_mode(px, py, color, mode, coverage);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::float_image::{FImage, Pixel, PixelFormat};
+
+// Hues are in degrees, Lab/LCh/Luv lightness is in [0, 100] and everything else is nominally in [0, 1].
+// XYZ, Lab, LCh and Luv use the D65 white point, YCbCr is full range BT.601 with chroma centered on 0.5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorSpace {
+    Srgb,
+    LinearSrgb,
+    Hsv,
+    Hsl,
+    Xyz,
+    Lab,
+    Lch,
+    Luv,
+    YCbCr
+}
+
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+const LINEAR_TO_XYZ: [[f32; 3]; 3] = [[0.4124564, 0.3575761, 0.1804375],
+                                      [0.2126729, 0.7151522, 0.0721750],
+                                      [0.0193339, 0.119192, 0.9503041]];
+
+const XYZ_TO_LINEAR: [[f32; 3]; 3] = [[3.2404542, -1.5371385, -0.4985314],
+                                      [-0.969266, 1.8760108, 0.0415560],
+                                      [0.0556434, -0.2040259, 1.0572252]];
+
+const EPSILON: f32 = 216.0 / 24389.0; // (6/29)^3
+const KAPPA: f32 = 24389.0 / 27.0; // (29/3)^3
+
+fn mat_mul(m: &[[f32; 3]; 3], c: [f32; 3]) -> [f32; 3] {
+    [m[0][0] * c[0] + m[0][1] * c[1] + m[0][2] * c[2],
+     m[1][0] * c[0] + m[1][1] * c[1] + m[1][2] * c[2],
+     m[2][0] * c[0] + m[2][1] * c[1] + m[2][2] * c[2]]
+}
+
+// the transfer functions are mirrored for negative values so out of range colors survive a round trip
+pub fn srgb_to_linear(c: f32) -> f32 {
+    let a = c.abs();
+    let l = if a <= 0.04045 { a / 12.92 } else { ((a + 0.055) / 1.055).powf(2.4) };
+
+    l.copysign(c)
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let a = c.abs();
+    let s = if a <= 0.0031308 { a * 12.92 } else { 1.055 * a.powf(1.0 / 2.4) - 0.055 };
+
+    s.copysign(c)
+}
+
+fn rgb_to_hsv(c: [f32; 3]) -> [f32; 3] {
+    let max = c[0].max(c[1]).max(c[2]);
+    let min = c[0].min(c[1]).min(c[2]);
+
+    let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+
+    [hue(c, max, min), s, max]
+}
+
+fn hsv_to_rgb(c: [f32; 3]) -> [f32; 3] {
+    let chroma = c[2] * c[1];
+
+    from_hue(c[0], chroma, c[2] - chroma)
+}
+
+fn rgb_to_hsl(c: [f32; 3]) -> [f32; 3] {
+    let max = c[0].max(c[1]).max(c[2]);
+    let min = c[0].min(c[1]).min(c[2]);
+
+    let l = (max + min) / 2.0;
+    let d = 1.0 - (2.0 * l - 1.0).abs();
+    let s = if max == min || d == 0.0 { 0.0 } else { (max - min) / d };
+
+    [hue(c, max, min), s, l]
+}
+
+fn hsl_to_rgb(c: [f32; 3]) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * c[2] - 1.0).abs()) * c[1];
+
+    from_hue(c[0], chroma, c[2] - chroma / 2.0)
+}
+
+fn hue(c: [f32; 3], max: f32, min: f32) -> f32 {
+    let d = max - min;
+
+    let h = if d == 0.0 {
+        0.0
+    } else if max == c[0] {
+        ((c[1] - c[2]) / d).rem_euclid(6.0)
+    } else if max == c[1] {
+        (c[2] - c[0]) / d + 2.0
+    } else {
+        (c[0] - c[1]) / d + 4.0
+    };
+
+    h * 60.0
+}
+
+fn from_hue(h: f32, chroma: f32, m: f32) -> [f32; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x)
+    };
+
+    [r + m, g + m, b + m]
+}
+
+fn lab_f(t: f32) -> f32 {
+    if t > EPSILON { t.cbrt() } else { (KAPPA * t + 16.0) / 116.0 }
+}
+
+fn lab_f_inv(t: f32) -> f32 {
+    let t3 = t * t * t;
+    if t3 > EPSILON { t3 } else { (116.0 * t - 16.0) / KAPPA }
+}
+
+fn xyz_to_lab(c: [f32; 3]) -> [f32; 3] {
+    let fx = lab_f(c[0] / WHITE[0]);
+    let fy = lab_f(c[1] / WHITE[1]);
+    let fz = lab_f(c[2] / WHITE[2]);
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_xyz(c: [f32; 3]) -> [f32; 3] {
+    let fy = (c[0] + 16.0) / 116.0;
+    let fx = fy + c[1] / 500.0;
+    let fz = fy - c[2] / 200.0;
+
+    [lab_f_inv(fx) * WHITE[0], lab_f_inv(fy) * WHITE[1], lab_f_inv(fz) * WHITE[2]]
+}
+
+fn lab_to_lch(c: [f32; 3]) -> [f32; 3] {
+    let h = c[2].atan2(c[1]).to_degrees().rem_euclid(360.0);
+
+    [c[0], c[1].hypot(c[2]), h]
+}
+
+fn lch_to_lab(c: [f32; 3]) -> [f32; 3] {
+    let h = c[2].to_radians();
+
+    [c[0], c[1] * h.cos(), c[1] * h.sin()]
+}
+
+fn uv_prime(c: [f32; 3]) -> (f32, f32) {
+    let d = c[0] + 15.0 * c[1] + 3.0 * c[2];
+    if d == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    (4.0 * c[0] / d, 9.0 * c[1] / d)
+}
+
+fn xyz_to_luv(c: [f32; 3]) -> [f32; 3] {
+    let yr = c[1] / WHITE[1];
+    let l = if yr > EPSILON { 116.0 * yr.cbrt() - 16.0 } else { KAPPA * yr };
+
+    if l == 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+
+    let (u, v) = uv_prime(c);
+    let (un, vn) = uv_prime(WHITE);
+
+    [l, 13.0 * l * (u - un), 13.0 * l * (v - vn)]
+}
+
+fn luv_to_xyz(c: [f32; 3]) -> [f32; 3] {
+    if c[0] == 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+
+    let (un, vn) = uv_prime(WHITE);
+    let u = c[1] / (13.0 * c[0]) + un;
+    let v = c[2] / (13.0 * c[0]) + vn;
+
+    let y = if c[0] > KAPPA * EPSILON { ((c[0] + 16.0) / 116.0).powi(3) } else { c[0] / KAPPA } * WHITE[1];
+
+    if v == 0.0 {
+        return [0.0, y, 0.0];
+    }
+
+    [y * 9.0 * u / (4.0 * v), y, y * (12.0 - 3.0 * u - 20.0 * v) / (4.0 * v)]
+}
+
+fn rgb_to_ycbcr(c: [f32; 3]) -> [f32; 3] {
+    let y = 0.299 * c[0] + 0.587 * c[1] + 0.114 * c[2];
+
+    [y, 0.5 + (c[2] - y) / 1.772, 0.5 + (c[0] - y) / 1.402]
+}
+
+fn ycbcr_to_rgb(c: [f32; 3]) -> [f32; 3] {
+    let cb = c[1] - 0.5;
+    let cr = c[2] - 0.5;
+
+    [c[0] + 1.402 * cr, c[0] - 0.344136 * cb - 0.714136 * cr, c[0] + 1.772 * cb]
+}
+
+fn map3(c: [f32; 3], f: fn(f32) -> f32) -> [f32; 3] {
+    [f(c[0]), f(c[1]), f(c[2])]
+}
+
+fn to_linear(c: [f32; 3], from: ColorSpace) -> [f32; 3] {
+    match from {
+        ColorSpace::Srgb => map3(c, srgb_to_linear),
+        ColorSpace::LinearSrgb => c,
+        ColorSpace::Hsv => map3(hsv_to_rgb(c), srgb_to_linear),
+        ColorSpace::Hsl => map3(hsl_to_rgb(c), srgb_to_linear),
+        ColorSpace::YCbCr => map3(ycbcr_to_rgb(c), srgb_to_linear),
+        ColorSpace::Xyz => mat_mul(&XYZ_TO_LINEAR, c),
+        ColorSpace::Lab => mat_mul(&XYZ_TO_LINEAR, lab_to_xyz(c)),
+        ColorSpace::Lch => mat_mul(&XYZ_TO_LINEAR, lab_to_xyz(lch_to_lab(c))),
+        ColorSpace::Luv => mat_mul(&XYZ_TO_LINEAR, luv_to_xyz(c))
+    }
+}
+
+fn from_linear(c: [f32; 3], to: ColorSpace) -> [f32; 3] {
+    match to {
+        ColorSpace::Srgb => map3(c, linear_to_srgb),
+        ColorSpace::LinearSrgb => c,
+        ColorSpace::Hsv => rgb_to_hsv(map3(c, linear_to_srgb)),
+        ColorSpace::Hsl => rgb_to_hsl(map3(c, linear_to_srgb)),
+        ColorSpace::YCbCr => rgb_to_ycbcr(map3(c, linear_to_srgb)),
+        ColorSpace::Xyz => mat_mul(&LINEAR_TO_XYZ, c),
+        ColorSpace::Lab => xyz_to_lab(mat_mul(&LINEAR_TO_XYZ, c)),
+        ColorSpace::Lch => lab_to_lch(xyz_to_lab(mat_mul(&LINEAR_TO_XYZ, c))),
+        ColorSpace::Luv => xyz_to_luv(mat_mul(&LINEAR_TO_XYZ, c))
+    }
+}
+
+pub fn convert(c: [f32; 3], from: ColorSpace, to: ColorSpace) -> [f32; 3] {
+    if from == to {
+        return c;
+    }
+
+    // the spaces derived from gamma encoded sRGB can skip the trip through linear light
+    match (from, to) {
+        (ColorSpace::Srgb, ColorSpace::Hsv) => rgb_to_hsv(c),
+        (ColorSpace::Srgb, ColorSpace::Hsl) => rgb_to_hsl(c),
+        (ColorSpace::Srgb, ColorSpace::YCbCr) => rgb_to_ycbcr(c),
+        (ColorSpace::Hsv, ColorSpace::Srgb) => hsv_to_rgb(c),
+        (ColorSpace::Hsl, ColorSpace::Srgb) => hsl_to_rgb(c),
+        (ColorSpace::YCbCr, ColorSpace::Srgb) => ycbcr_to_rgb(c),
+        _ => from_linear(to_linear(c, from), to)
+    }
+}
+
+// the component that carries lightness for grays, which is what Mono pixels store
+fn gray_component(space: ColorSpace) -> usize {
+    match space {
+        ColorSpace::Hsv | ColorSpace::Hsl => 2,
+        ColorSpace::Xyz => 1,
+        _ => 0
+    }
+}
+
+// the gray whose lightness component is v
+fn gray(v: f32, space: ColorSpace) -> [f32; 3] {
+    match space {
+        ColorSpace::Srgb | ColorSpace::LinearSrgb => [v; 3],
+        ColorSpace::Hsv | ColorSpace::Hsl => [0.0, 0.0, v],
+        ColorSpace::Xyz => [v * WHITE[0], v, v * WHITE[2]],
+        ColorSpace::Lab | ColorSpace::Lch | ColorSpace::Luv => [v, 0.0, 0.0],
+        ColorSpace::YCbCr => [v, 0.5, 0.5]
+    }
+}
+
+impl Pixel<'_> {
+    // Mono pixels are grays and keep their lightness component (V, L, Y...), alpha is left alone
+    pub fn convert_color_space(&self, from: ColorSpace, to: ColorSpace) -> Pixel<'static> {
+        if self.format() == PixelFormat::Mono {
+            return Pixel::mono(convert(gray(self.r(), from), from, to)[gray_component(to)]);
+        }
+
+        let c = convert([self.r(), self.g(), self.b()], from, to);
+
+        match self.format() {
+            PixelFormat::RGBA => Pixel::rgba(c[0], c[1], c[2], self.a()),
+            _ => Pixel::rgb(c[0], c[1], c[2])
+        }
+    }
+}
+
+impl FImage {
+    pub fn to_color_space(&self, to: ColorSpace) -> FImage {
+        let mut out = self.clone();
+        out.convert_color_space(to);
+
+        out
+    }
+
+    pub fn convert_color_space(&mut self, to: ColorSpace) {
+        let from = self.color_space();
+        if from == to {
+            return;
+        }
+
+        for x in 0..self.width() as i32 {
+            for y in 0..self.height() as i32 {
+                let p = self.get_pixel(x, y).convert_color_space(from, to);
+                self.set_pixel(x, y, p);
+            }
+        }
+
+        self.set_color_space(to);
+    }
+
+    // runs func on a linear light copy of the image and converts the result back to this image's color space
+    pub fn in_linear_light<FN: FnOnce(&FImage) -> FImage>(&self, func: FN) -> FImage {
+        let mut out = func(&self.to_color_space(ColorSpace::LinearSrgb));
+        out.convert_color_space(self.color_space());
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, ColorSpace};
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+
+    const SPACES: [ColorSpace; 9] = [ColorSpace::Srgb, ColorSpace::LinearSrgb, ColorSpace::Hsv, ColorSpace::Hsl, ColorSpace::Xyz,
+                                     ColorSpace::Lab, ColorSpace::Lch, ColorSpace::Luv, ColorSpace::YCbCr];
+
+    fn close(a: [f32; 3], b: [f32; 3], tolerance: f32) -> bool {
+        a.iter().zip(b).all(|(x, y)| (x - y).abs() <= tolerance)
+    }
+
+    #[test]
+    fn known_values() {
+        assert!(close(convert([1.0, 1.0, 1.0], ColorSpace::Srgb, ColorSpace::Lab), [100.0, 0.0, 0.0], 1e-2));
+        assert!(close(convert([1.0, 0.0, 0.0], ColorSpace::Srgb, ColorSpace::Hsv), [0.0, 1.0, 1.0], 1e-6));
+        assert!(close(convert([0.0, 0.0, 1.0], ColorSpace::Srgb, ColorSpace::Hsl), [240.0, 1.0, 0.5], 1e-4));
+        assert!(close(convert([0.5, 0.5, 0.5], ColorSpace::Srgb, ColorSpace::LinearSrgb), [0.21404; 3], 1e-5));
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let colors = [[0.2, 0.5, 0.8], [0.9, 0.1, 0.3], [0.5, 0.5, 0.5], [0.05, 0.95, 0.6]];
+        for c in colors {
+            for space in SPACES {
+                let back = convert(convert(c, ColorSpace::Srgb, space), space, ColorSpace::Srgb);
+                assert!(close(back, c, 1e-3), "{:?} through {:?} came back as {:?}", c, space, back);
+            }
+        }
+    }
+
+    #[test]
+    fn mono_keeps_its_lightness() {
+        for space in SPACES {
+            let p = Pixel::mono(0.6).convert_color_space(ColorSpace::Srgb, space);
+            assert!(p.r() > 0.1, "gray turned black in {:?}", space);
+
+            let back = p.convert_color_space(space, ColorSpace::Srgb);
+            assert!((back.r() - 0.6).abs() < 1e-4, "{:?} came back as {}", space, back.r());
+        }
+
+        assert_eq!(Pixel::mono(0.6).convert_color_space(ColorSpace::Srgb, ColorSpace::Hsv).r(), 0.6);
+    }
+
+    #[test]
+    fn images_keep_their_tag_and_alpha() {
+        let mut img = FImage::new(2, 1, PixelFormat::RGBA);
+        img.set_pixel(0, 0, Pixel::rgba(0.2, 0.4, 0.6, 0.25));
+        let lab = img.to_color_space(ColorSpace::Lab);
+
+        assert_eq!(lab.color_space(), ColorSpace::Lab);
+        assert_eq!(lab.get_pixel(0, 0).a(), 0.25);
+        let back = lab.to_color_space(ColorSpace::Srgb);
+        assert!(close([back.get_pixel(0, 0).r(), back.get_pixel(0, 0).g(), back.get_pixel(0, 0).b()], [0.2, 0.4, 0.6], 1e-4));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::float_image::{FImage, Pixel, PixelFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompositeOp {
+    Over,
+    In,
+    Out,
+    Atop,
+    Xor,
+    Plus
+}
+
+impl CompositeOp {
+    // Porter-Duff fractions (Fa, Fb) of source and destination
+    fn fractions(&self, src_a: f32, dst_a: f32) -> (f32, f32) {
+        match self {
+            CompositeOp::Over => (1.0, 1.0 - src_a),
+            CompositeOp::In => (dst_a, 0.0),
+            CompositeOp::Out => (1.0 - dst_a, 0.0),
+            CompositeOp::Atop => (dst_a, 1.0 - src_a),
+            CompositeOp::Xor => (1.0 - dst_a, 1.0 - src_a),
+            CompositeOp::Plus => (1.0, 1.0)
+        }
+    }
+}
+
+pub fn premultiply(c: [f32; 4]) -> [f32; 4] {
+    [c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]]
+}
+
+pub fn unpremultiply(c: [f32; 4]) -> [f32; 4] {
+    if c[3] == 0.0 {
+        return [0.0; 4];
+    }
+
+    [c[0] / c[3], c[1] / c[3], c[2] / c[3], c[3]]
+}
+
+// both colors and the result are premultiplied
+pub fn composite_premultiplied(src: [f32; 4], dst: [f32; 4], op: CompositeOp) -> [f32; 4] {
+    let (fa, fb) = op.fractions(src[3], dst[3]);
+
+    let mut out = [0.0; 4];
+    for i in 0..4 {
+        out[i] = fa * src[i] + fb * dst[i];
+    }
+
+    if op == CompositeOp::Plus {
+        out[3] = out[3].min(1.0);
+    }
+
+    out
+}
+
+impl Pixel<'_> {
+    fn rgba_array(&self) -> [f32; 4] {
+        [self.r(), self.g(), self.b(), self.a()]
+    }
+
+    pub fn premultiply(&self) -> Pixel<'static> {
+        let c = premultiply(self.rgba_array());
+
+        Pixel::rgba(c[0], c[1], c[2], c[3]).into_format(self.format())
+    }
+
+    pub fn unpremultiply(&self) -> Pixel<'static> {
+        let c = unpremultiply(self.rgba_array());
+
+        Pixel::rgba(c[0], c[1], c[2], c[3]).into_format(self.format())
+    }
+
+    // Composites this pixel onto dst, both with straight alpha. The result has dst's format,
+    // destinations without an alpha channel count as opaque and store the result flattened onto black,
+    // mono ones as its luma.
+    pub fn composite(&self, dst: &Pixel, op: CompositeOp) -> Pixel<'static> {
+        let out = composite_premultiplied(premultiply(self.rgba_array()), premultiply(dst.rgba_array()), op);
+
+        match dst.format() {
+            PixelFormat::RGBA => {
+                let c = unpremultiply(out);
+                Pixel::rgba(c[0], c[1], c[2], c[3])
+            },
+            PixelFormat::RGB => Pixel::rgb(out[0], out[1], out[2]),
+            PixelFormat::Mono => Pixel::mono(Pixel::rgb(out[0], out[1], out[2]).luma())
+        }
+    }
+
+    fn into_format(self, format: PixelFormat) -> Pixel<'static> {
+        match format {
+            PixelFormat::Mono => Pixel::mono(self.r()),
+            PixelFormat::RGB => Pixel::rgb(self.r(), self.g(), self.b()),
+            PixelFormat::RGBA => Pixel::rgba(self.r(), self.g(), self.b(), self.a())
+        }
+    }
+}
+
+impl FImage {
+    // images without an alpha channel are left unchanged
+    pub fn premultiply(&mut self) {
+        self.map_rgba(premultiply);
+    }
+
+    pub fn unpremultiply(&mut self) {
+        self.map_rgba(unpremultiply);
+    }
+
+    fn map_rgba(&mut self, func: fn([f32; 4]) -> [f32; 4]) {
+        if self.get_pixel_format() != PixelFormat::RGBA {
+            return;
+        }
+
+        for x in 0..self.width() as i32 {
+            for y in 0..self.height() as i32 {
+                let c = func(self.get_pixel(x, y).rgba_array());
+                self.set_pixel(x, y, Pixel::rgba(c[0], c[1], c[2], c[3]));
+            }
+        }
+    }
+
+    pub fn set_pixel_composite(&mut self, x: i32, y: i32, pixel: Pixel, op: CompositeOp) {
+        if self.write_coords(x, y).is_none() {
+            return;
+        }
+
+        let new_pixel = pixel.composite(&self.get_pixel(x, y), op);
+
+        self.set_pixel(x, y, new_pixel);
+    }
+}
+
+// composites src onto dst, the result takes dst's format, border mode and color space
+pub fn composite_images(src: &FImage, dst: &FImage, op: CompositeOp) -> FImage {
+    let mut out = dst.clone();
+
+    for x in 0..dst.width() as i32 {
+        for y in 0..dst.height() as i32 {
+            out.set_pixel_composite(x, y, src.get_pixel(x, y), op);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{composite_images, CompositeOp};
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
+    fn filled(width: usize, height: usize, pixel: Pixel) -> FImage {
+        let mut img = FImage::new(width, height, pixel.format());
+        for x in 0..width as i32 {
+            for y in 0..height as i32 {
+                img.set_pixel(x, y, pixel);
+            }
+        }
+
+        img
+    }
+
+    #[test]
+    fn porter_duff_operators() {
+        let src = Pixel::rgba(1.0, 0.0, 0.0, 0.5);
+        let dst = Pixel::rgba(0.0, 0.0, 1.0, 0.5);
+
+        assert!(close(src.composite(&dst, CompositeOp::Over).slice(), &[2.0 / 3.0, 0.0, 1.0 / 3.0, 0.75]));
+        assert!(close(src.composite(&dst, CompositeOp::In).slice(), &[1.0, 0.0, 0.0, 0.25]));
+        assert!(close(src.composite(&dst, CompositeOp::Out).slice(), &[1.0, 0.0, 0.0, 0.25]));
+        assert!(close(src.composite(&dst, CompositeOp::Atop).slice(), &[0.5, 0.0, 0.5, 0.5]));
+        assert!(close(src.composite(&dst, CompositeOp::Xor).slice(), &[0.5, 0.0, 0.5, 0.5]));
+        assert!(close(src.composite(&dst, CompositeOp::Plus).slice(), &[0.5, 0.0, 0.5, 1.0]));
+    }
+
+    #[test]
+    fn premultiply_round_trips() {
+        let p = Pixel::rgba(0.8, 0.4, 0.2, 0.5);
+        assert!(close(p.premultiply().slice(), &[0.4, 0.2, 0.1, 0.5]));
+        assert!(close(p.premultiply().unpremultiply().slice(), p.slice()));
+        assert_eq!(Pixel::rgba(0.8, 0.4, 0.2, 0.0).premultiply().unpremultiply().slice(), &[0.0; 4]);
+    }
+
+    #[test]
+    fn destinations_keep_their_format() {
+        let src = Pixel::rgba(0.0, 1.0, 0.0, 0.5);
+
+        assert!(close(src.composite(&Pixel::rgb(0.0, 0.0, 1.0), CompositeOp::Over).slice(), &[0.0, 0.5, 0.5]));
+        assert!(close(src.composite(&Pixel::mono(0.2), CompositeOp::Over).slice(), &[0.5 * 0.7152 + 0.5 * 0.2]));
+
+        let dst = filled(2, 2, Pixel::mono(0.2));
+        let top = filled(2, 2, Pixel::rgba(0.0, 1.0, 0.0, 0.5));
+        let out = composite_images(&top, &dst, CompositeOp::Over);
+        assert_eq!(out.get_pixel_format(), PixelFormat::Mono);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert!(close(out.get_pixel(x, y).slice(), &[0.5 * 0.7152 + 0.5 * 0.2]));
+        }
+    }
+
+    #[test]
+    fn composites_outside_only_land_when_wrapping() {
+        let mut img = filled(2, 1, Pixel::rgba(0.0, 0.0, 0.0, 1.0));
+        img.set_pixel_composite(-1, 0, Pixel::rgba(1.0, 1.0, 1.0, 1.0), CompositeOp::Over);
+        assert_eq!(img.get_pixel(1, 0).slice(), &[1.0; 4]);
+
+        img.set_border_mode(BorderMode::Clamp);
+        img.set_pixel_composite(-1, 0, Pixel::rgba(0.5, 0.5, 0.5, 1.0), CompositeOp::Over);
+        assert_eq!(img.get_pixel(0, 0).slice(), &[0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(img.get_pixel(1, 0).slice(), &[1.0; 4]);
+    }
+}
@@ -0,0 +1,321 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use image::{GenericImage, Primitive};
+
+use crate::error::Result;
+use crate::float_image::{subpixel_range, FImage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    // round to nearest
+    None,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    // ordered dither with a 2^order square threshold matrix
+    Bayer { order: u32 },
+    // ordered dither with a 64x64 void-and-cluster threshold texture
+    BlueNoise
+}
+
+// (dx, dy, weight) of the error diffusion kernels, for rows scanned left to right
+const FLOYD_STEINBERG: &[(i32, i32, f32)] = &[(1, 0, 7.0 / 16.0),
+                                              (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0)];
+
+// Atkinson only spreads 6/8 of the error, which keeps highlights and shadows clean
+const ATKINSON: &[(i32, i32, f32)] = &[(1, 0, 1.0 / 8.0), (2, 0, 1.0 / 8.0),
+                                       (-1, 1, 1.0 / 8.0), (0, 1, 1.0 / 8.0), (1, 1, 1.0 / 8.0),
+                                       (0, 2, 1.0 / 8.0)];
+
+const JARVIS_JUDICE_NINKE: &[(i32, i32, f32)] = &[(1, 0, 7.0 / 48.0), (2, 0, 5.0 / 48.0),
+                                                  (-2, 1, 3.0 / 48.0), (-1, 1, 5.0 / 48.0), (0, 1, 7.0 / 48.0), (1, 1, 5.0 / 48.0), (2, 1, 3.0 / 48.0),
+                                                  (-2, 2, 1.0 / 48.0), (-1, 2, 3.0 / 48.0), (0, 2, 5.0 / 48.0), (1, 2, 3.0 / 48.0), (2, 2, 1.0 / 48.0)];
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// thresholds in (0, 1), row major
+fn bayer_matrix(order: u32) -> Vec<f32> {
+    let mut m = vec![0u32];
+    let mut size = 1;
+
+    for _ in 0..order {
+        let mut next = vec![0; size * size * 4];
+        for y in 0..size {
+            for x in 0..size {
+                let v = 4 * m[x + y * size];
+                next[x + y * size * 2] = v;
+                next[x + size + y * size * 2] = v + 2;
+                next[x + (y + size) * size * 2] = v + 3;
+                next[x + size + (y + size) * size * 2] = v + 1;
+            }
+        }
+        m = next;
+        size *= 2;
+    }
+
+    let n = (size * size) as f32;
+    m.into_iter().map(|v| (v as f32 + 0.5) / n).collect()
+}
+
+fn blue_noise() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+
+    TEXTURE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+// Ulichney's void-and-cluster method on a torus, returns thresholds in (0, 1)
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let n = size * size;
+
+    // gaussian of the wrapped distance between two cells
+    let mut kernel = vec![0.0f32; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = dx.min(size - dx) as f32;
+            let wy = dy.min(size - dy) as f32;
+            kernel[dx + dy * size] = (-(wx * wx + wy * wy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+
+    let update = |energy: &mut [f32], cell: usize, sign: f32| {
+        let (cx, cy) = (cell % size, cell / size);
+        for y in 0..size {
+            for x in 0..size {
+                let k = (x + size - cx) % size + (y + size - cy) % size * size;
+                energy[x + y * size] += sign * kernel[k];
+            }
+        }
+    };
+
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap_or(0)
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap_or(0)
+    };
+
+    // fixed seed so the texture is the same every run
+    let mut state = 0x2545f491u32;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0f32; n];
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        let cell = random() as usize % n;
+        if !pattern[cell] {
+            pattern[cell] = true;
+            update(&mut energy, cell, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Move points from the tightest cluster to the largest void until the pattern settles. Ties in
+    // the energy can make it cycle instead, so it gives up after one swap per cell.
+    for _ in 0..n {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; n];
+
+    // ranks below the initial pattern, taking away clusters
+    let mut reduced = pattern.clone();
+    let mut reduced_energy = energy.clone();
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&reduced, &reduced_energy);
+        reduced[cluster] = false;
+        update(&mut reduced_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // ranks above it, filling voids
+    for r in initial..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.into_iter().map(|r| (r as f32 + 0.5) / n as f32).collect()
+}
+
+impl Dither {
+    // (dx, dy, weight) offsets of the error diffusion modes
+    pub(crate) fn diffusion_kernel(&self) -> Option<&'static [(i32, i32, f32)]> {
+        match self {
+            Dither::FloydSteinberg => Some(FLOYD_STEINBERG),
+            Dither::Atkinson => Some(ATKINSON),
+            Dither::JarvisJudiceNinke => Some(JARVIS_JUDICE_NINKE),
+            _ => None
+        }
+    }
+
+    // (row major thresholds in (0, 1), side length) of the ordered modes, the blue noise texture is borrowed
+    pub(crate) fn thresholds(&self) -> Option<(Cow<'static, [f32]>, usize)> {
+        match *self {
+            Dither::Bayer { order } => {
+                let order = order.min(8);
+                Some((Cow::Owned(bayer_matrix(order)), 1 << order))
+            },
+            Dither::BlueNoise => Some((Cow::Borrowed(blue_noise()), BLUE_NOISE_SIZE)),
+            _ => None
+        }
+    }
+}
+
+impl FImage {
+    // Snaps every value to one of `levels` evenly spaced steps in [0, 1], clipping out of range values.
+    // The color channels are dithered, alpha is only rounded.
+    pub fn quantize(&mut self, levels: u32, dither: Dither) {
+        let max = (levels.max(2) - 1) as f32;
+        let channels = self.get_pixel_format().channel_count();
+        let color = channels.min(3);
+
+        for v in self.data_mut().iter_mut() {
+            *v = v.clamp(0.0, 1.0) * max;
+        }
+
+        if let Some(kernel) = dither.diffusion_kernel() {
+            self.diffuse_error(kernel, color, max);
+        } else if let Some((thresholds, size)) = dither.thresholds() {
+            self.ordered_dither(&thresholds, size, color);
+        } else {
+            self.data_mut().iter_mut().for_each(|v| *v = v.round());
+        }
+
+        for (i, v) in self.data_mut().iter_mut().enumerate() {
+            let q = if i % channels < color { *v } else { v.round() };
+            *v = q.clamp(0.0, max) / max;
+        }
+    }
+
+    // serpentine scan, odd rows run right to left with the kernel mirrored
+    fn diffuse_error(&mut self, kernel: &[(i32, i32, f32)], color: usize, max: f32) {
+        let (w, h) = (self.width() as i32, self.height() as i32);
+        let channels = self.get_pixel_format().channel_count();
+        let data = self.data_mut();
+
+        for y in 0..h {
+            let reverse = y % 2 == 1;
+            for i in 0..w {
+                let x = if reverse { w - 1 - i } else { i };
+                let offset = (x + y * w) as usize * channels;
+
+                for c in 0..color {
+                    let old = data[offset + c];
+                    let new = old.round().clamp(0.0, max);
+                    data[offset + c] = new;
+                    let err = old - new;
+
+                    for &(dx, dy, weight) in kernel {
+                        let (nx, ny) = (if reverse { x - dx } else { x + dx }, y + dy);
+                        if nx >= 0 && nx < w && ny < h {
+                            data[(nx + ny * w) as usize * channels + c] += err * weight;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn ordered_dither(&mut self, thresholds: &[f32], size: usize, color: usize) {
+        for (x, y, px) in self.enumerate_pixels_mut() {
+            let t = thresholds[x % size + (y % size) * size];
+            for v in px[..color].iter_mut() {
+                *v = (*v + t).floor();
+            }
+        }
+    }
+
+    // dithers down to the buffer's bit depth first, float buffers and integer ones with steps finer than
+    // f32 precision (u32) are copied as they are
+    pub fn copy_to_image_buffer_dithered<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&self, image: &mut I, dither: Dither) {
+        self.try_copy_to_image_buffer_dithered(image, dither).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_copy_to_image_buffer_dithered<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&self, image: &mut I, dither: Dither) -> Result<()> {
+        let (max, is_float) = subpixel_range::<SP>();
+        if is_float || max >= (1u32 << f32::MANTISSA_DIGITS) as f32 {
+            return self.try_copy_to_image_buffer(image);
+        }
+
+        let mut quantized = self.clone();
+        quantized.quantize(max as u32 + 1, dither);
+
+        quantized.try_copy_to_image_buffer(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma};
+
+    use super::{bayer_matrix, void_and_cluster, Dither};
+    use crate::float_image::{FImage, PixelFormat};
+
+    #[test]
+    fn threshold_matrices_are_permutations() {
+        assert_eq!(bayer_matrix(1), vec![0.125, 0.625, 0.875, 0.375]);
+
+        for mut t in [bayer_matrix(3), void_and_cluster(16, 1.5)] {
+            let n = t.len();
+            t.sort_by(f32::total_cmp);
+            assert!(t.iter().enumerate().all(|(i, &v)| v == (i as f32 + 0.5) / n as f32));
+        }
+    }
+
+    #[test]
+    fn dithering_keeps_the_mean() {
+        let modes = [Dither::FloydSteinberg, Dither::Atkinson, Dither::JarvisJudiceNinke, Dither::Bayer { order: 3 }, Dither::BlueNoise];
+
+        for dither in modes {
+            let mut img = FImage::new(64, 64, PixelFormat::RGBA);
+            img.data_mut().chunks_exact_mut(4).for_each(|p| p.copy_from_slice(&[0.3, 0.7, 1.5, 0.4]));
+            img.quantize(2, dither);
+
+            assert!(img.data().iter().all(|&v| v == 0.0 || v == 1.0), "{:?}", dither);
+            let mean = |c: usize| img.data().iter().skip(c).step_by(4).sum::<f32>() / 4096.0;
+            // Atkinson drops a quarter of the error, the others keep the mean
+            let tolerance = if dither == Dither::Atkinson { 0.1 } else { 0.02 };
+            assert!((mean(0) - 0.3).abs() < tolerance && (mean(1) - 0.7).abs() < tolerance, "{:?}", dither);
+            // out of range values clip, alpha is only rounded
+            assert_eq!((mean(2), mean(3)), (1.0, 0.0), "{:?}", dither);
+        }
+
+        let mut img = FImage::new(3, 1, PixelFormat::Mono);
+        img.data_mut().copy_from_slice(&[0.1, 0.4, 0.9]);
+        img.quantize(3, Dither::None);
+        assert_eq!(img.data(), &[0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn wide_integer_buffers_are_copied_undithered() {
+        let mut img = FImage::new(4, 1, PixelFormat::Mono);
+        img.data_mut().copy_from_slice(&[0.0, 0.25, 0.5, 1.0]);
+
+        let mut dithered = ImageBuffer::<Luma<u32>, Vec<u32>>::new(4, 1);
+        img.copy_to_image_buffer_dithered(&mut dithered, Dither::FloydSteinberg);
+        let mut plain = ImageBuffer::<Luma<u32>, Vec<u32>>::new(4, 1);
+        img.copy_to_image_buffer(&mut plain);
+
+        assert_eq!(dithered, plain);
+        assert_eq!(dithered.get_pixel(3, 0).0[0], u32::MAX);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io;
+
+use image::ImageError;
+
+use crate::float_image::{BitDepth, PixelFormat};
+use crate::image_io::FileFormat;
+use crate::image_view::Rect;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidHex(String),
+    InvalidPixelLength(usize),
+    DimensionMismatch { expected: (usize, usize), found: (usize, usize) },
+    EvenFilterSize(usize),
+    UnsupportedPixelFormat { expected: PixelFormat, found: PixelFormat },
+    InvalidRect { rect: Rect, width: usize, height: usize },
+    SingularTransform,
+    InvalidChannel { channel: usize, format: PixelFormat },
+    InvalidChannelCount(usize),
+    UnsupportedBitDepth { format: FileFormat, depth: BitDepth },
+    Codec(String),
+    UnsupportedVersion(u16),
+    ChecksumMismatch { expected: u32, found: u32 },
+    Io(io::Error),
+    Image(ImageError)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidHex(hex) => write!(f, "invalid hex color: {:?}", hex),
+            Error::InvalidPixelLength(len) => write!(f, "invalid pixel slice length: {} (expected 1, 3 or 4)", len),
+            Error::DimensionMismatch { expected, found } => write!(f, "dimensions do not match: expected {}x{}, found {}x{}", expected.0, expected.1, found.0, found.1),
+            Error::EvenFilterSize(size) => write!(f, "filter size must be odd, got {}", size),
+            Error::UnsupportedPixelFormat { expected, found } => write!(f, "unsupported pixel format: expected {:?}, found {:?}", expected, found),
+            Error::InvalidRect { rect, width, height } => write!(f, "rect {}x{} at ({}, {}) does not fit in a {}x{} image", rect.width, rect.height, rect.x, rect.y, width, height),
+            Error::SingularTransform => write!(f, "transform matrix is not invertible"),
+            Error::InvalidChannel { channel, format } => write!(f, "channel {} does not exist in {:?} images", channel, format),
+            Error::InvalidChannelCount(count) => write!(f, "invalid channel count: {} (expected 1, 3 or 4)", count),
+            Error::UnsupportedBitDepth { format, depth } => write!(f, "{} images can't be stored at {:?}", format, depth),
+            Error::Codec(msg) => write!(f, "codec error: {}", msg),
+            Error::UnsupportedVersion(version) => write!(f, "unsupported format version: {}", version),
+            Error::ChecksumMismatch { expected, found } => write!(f, "checksum mismatch: expected {:08x}, found {:08x}", expected, found),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Image(e) => write!(f, "image error: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(e: ImageError) -> Error {
+        Error::Image(e)
+    }
+}
+
+impl From<exr::error::Error> for Error {
+    fn from(e: exr::error::Error) -> Error {
+        match e {
+            exr::error::Error::Io(e) => Error::Io(e),
+            other => Error::Codec(other.to_string())
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::Error;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+    use crate::image_filter::{try_watershed, FilterMatrix};
+
+    #[test]
+    fn bad_input_returns_errors() {
+        assert!(matches!(Pixel::try_from_hex("#12345z"), Err(Error::InvalidHex(_))));
+        assert!(matches!(Pixel::try_from_hex("#1234"), Err(Error::InvalidHex(_))));
+        assert!(matches!(Pixel::try_from_slice(&[0.0, 1.0]), Err(Error::InvalidPixelLength(2))));
+        assert!(matches!(FilterMatrix::try_new([[1.0; 4]; 4]), Err(Error::EvenFilterSize(4))));
+
+        let mut img = FImage::new(4, 3, PixelFormat::RGBA);
+        let mut buf = RgbaImage::new(3, 4);
+        assert!(matches!(img.try_copy_to_image_buffer(&mut buf), Err(Error::DimensionMismatch { expected: (4, 3), found: (3, 4) })));
+        assert!(matches!(img.try_copy_from_image_buffer(&buf), Err(Error::DimensionMismatch { .. })));
+        assert!(matches!(try_watershed(&img, (0, 0), (3, 2)), Err(Error::UnsupportedPixelFormat { .. })));
+    }
+
+    #[test]
+    fn good_input_still_parses() {
+        let p = Pixel::try_from_hex("#ff8000").unwrap();
+        assert_eq!(p.slice(), &[1.0, 128.0 / 255.0, 0.0]);
+        assert_eq!(Pixel::try_from_hex("0x00000080").unwrap().a(), 128.0 / 255.0);
+    }
+}
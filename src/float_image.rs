@@ -1,6 +1,12 @@
-use image::{GenericImage, Primitive};
+use image::{ColorType, DynamicImage, GenericImage, GrayImage, ImageBuffer, Luma, Primitive, Rgb, Rgb32FImage, RgbImage, Rgba, Rgba32FImage, RgbaImage};
 
-#[derive(Debug, Clone, Copy)]
+use crate::channels::LumaWeights;
+use crate::color_space::ColorSpace;
+use crate::compositing::CompositeOp;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PixelFormat {
     RGB,
     RGBA,
@@ -11,15 +17,20 @@ impl PixelFormat {
     pub fn channel_count(&self) -> usize {
         match self { PixelFormat::Mono => 1, PixelFormat::RGB => 3, PixelFormat::RGBA => 4 }
     }
+
+    pub fn from_channel_count(count: usize) -> Option<PixelFormat> {
+        match count { 1 => Some(PixelFormat::Mono), 3 => Some(PixelFormat::RGB), 4 => Some(PixelFormat::RGBA), _ => None }
+    }
 }
 
-#[derive(Debug, Clone)]
+// owned pixels are stored inline, only the first format.channel_count() values are used
+#[derive(Debug, Clone, Copy)]
 enum PixelData<'p> {
-    Owned(Box<[f32]>),
+    Owned([f32; 4]),
     Slice(&'p [f32])
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Pixel<'p> {
     data: PixelData<'p>,
     format: PixelFormat
@@ -27,46 +38,66 @@ pub struct Pixel<'p> {
 
 impl Pixel<'_> {
     pub fn rgb<'p>(r: f32, g: f32, b: f32) -> Pixel<'p> {
-        let data = Box::new([r, g, b]);
-        Pixel { data: PixelData::Owned(data), format: PixelFormat::RGB }
+        Pixel { data: PixelData::Owned([r, g, b, 0.0]), format: PixelFormat::RGB }
     }
 
     pub fn rgba<'p>(r: f32, g: f32, b: f32, a: f32) -> Pixel<'p> {
-        let data = Box::new([r, g, b, a]);
-        Pixel { data: PixelData::Owned(data), format: PixelFormat::RGBA }
+        Pixel { data: PixelData::Owned([r, g, b, a]), format: PixelFormat::RGBA }
     }
 
     pub fn mono<'p>(r: f32) -> Pixel<'p> {
-        let data = Box::new([r]);
-        Pixel { data: PixelData::Owned(data), format: PixelFormat::Mono }
+        Pixel { data: PixelData::Owned([r, 0.0, 0.0, 0.0]), format: PixelFormat::Mono }
     }
 
-    pub fn from_slice<'p>(slice: &'p [f32]) -> Pixel<'p> {
-        let format = match slice.len() {
-            1 => PixelFormat::Mono,
-            3 => PixelFormat::RGB,
-            4 => PixelFormat::RGBA,
-            _ => panic!("Invalid pixel slice!")
-        };
+    // values past the format's channel count are ignored
+    pub fn from_array<'p>(data: [f32; 4], format: PixelFormat) -> Pixel<'p> {
+        Pixel { data: PixelData::Owned(data), format }
+    }
+
+    fn format_for_len(len: usize) -> Result<PixelFormat> {
+        match len {
+            1 => Ok(PixelFormat::Mono),
+            3 => Ok(PixelFormat::RGB),
+            4 => Ok(PixelFormat::RGBA),
+            _ => Err(Error::InvalidPixelLength(len))
+        }
+    }
+
+    pub fn from_slice(slice: &[f32]) -> Pixel<'_> {
+        Pixel::try_from_slice(slice).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        Pixel { data: PixelData::Slice(slice), format }
+    pub fn try_from_slice(slice: &[f32]) -> Result<Pixel<'_>> {
+        let format = Pixel::format_for_len(slice.len())?;
+
+        Ok(Pixel { data: PixelData::Slice(slice), format })
     }
 
     pub fn from_boxed_slice<'p>(bx: Box<[f32]>) -> Pixel<'p> {
-        let format = match bx.len() {
-            1 => PixelFormat::Mono,
-            3 => PixelFormat::RGB,
-            4 => PixelFormat::RGBA,
-            _ => panic!("Invalid pixel slice!")
-        };
+        Pixel::try_from_boxed_slice(bx).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_from_boxed_slice<'p>(bx: Box<[f32]>) -> Result<Pixel<'p>> {
+        let format = Pixel::format_for_len(bx.len())?;
+
+        let mut data = [0.0; 4];
+        data[..bx.len()].copy_from_slice(&bx);
+
+        Ok(Pixel { data: PixelData::Owned(data), format })
+    }
+
+    // copies the values so the pixel no longer borrows from an image
+    pub fn to_owned_pixel(&self) -> Pixel<'static> {
+        let mut data = [0.0; 4];
+        data[..self.format.channel_count()].copy_from_slice(self.slice());
 
-        Pixel { data: PixelData::Owned(bx), format }
+        Pixel { data: PixelData::Owned(data), format: self.format }
     }
 
-    pub fn slice<'p>(&'p self) -> &'p [f32] {
+    pub fn slice(&self) -> &[f32] {
         match &self.data {
-            PixelData::Owned(d) => &d[..],
-            PixelData::Slice(s) => *s,
+            PixelData::Owned(d) => &d[..self.format.channel_count()],
+            PixelData::Slice(s) => s,
         }
     }
 
@@ -99,99 +130,231 @@ impl Pixel<'_> {
         self.format
     }
 
-    pub fn from_hex<'s>(hex: &'s str) -> Pixel {
+    // Rec. 709 weighted sum of the stored components
+    pub fn luma(&self) -> f32 {
+        LumaWeights::Rec709.luma(self.r(), self.g(), self.b())
+    }
+
+    // writes the pixel into 1, 3 or 4 channels, converting it the same way set_pixel does
+    pub fn write_to_slice(&self, out: &mut [f32]) {
+        out[0] = self.r();
+
+        if out.len() >= 3 {
+            out[1] = self.g();
+            out[2] = self.b();
+        }
+
+        if out.len() == 4 {
+            out[3] = self.a();
+        }
+    }
+
+    pub fn from_hex(hex: &str) -> Pixel<'_> {
+        Pixel::try_from_hex(hex).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_from_hex<'p>(hex: &str) -> Result<Pixel<'p>> {
         // trim prefix
-        let t = if hex.starts_with("#") {
-            &hex[1..]
-        } else if hex.starts_with("0x") {
-            &hex[2..]
-        } else {
-            hex
-        };
+        let t = hex.strip_prefix('#').or_else(|| hex.strip_prefix("0x")).unwrap_or(hex);
 
-        if t.len() == 6 { // does not contain alpha
-            let parsed = u32::from_str_radix(t, 16).unwrap();
+        if !t.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidHex(hex.to_owned()));
+        }
 
-            Pixel::rgb((parsed >> 16) as f32 / 255.0, ((parsed >> 8) & 0xFF) as f32 / 255.0, (parsed & 0xFF) as f32 / 255.0)
-        } else if t.len() == 8 { // does contain alpha
-            let parsed = u32::from_str_radix(t, 16).unwrap();
+        let parsed = u32::from_str_radix(t, 16).map_err(|_| Error::InvalidHex(hex.to_owned()))?;
 
-            Pixel::rgba((parsed >> 24) as f32 / 255.0, ((parsed >> 16) & 0xFF) as f32 / 255.0, ((parsed >> 8) & 0xFF) as f32 / 255.0, (parsed & 0xFF) as f32 / 255.0)
+        if t.len() == 6 { // does not contain alpha
+            Ok(Pixel::rgb((parsed >> 16) as f32 / 255.0, ((parsed >> 8) & 0xFF) as f32 / 255.0, (parsed & 0xFF) as f32 / 255.0))
+        } else if t.len() == 8 { // does contain alpha
+            Ok(Pixel::rgba((parsed >> 24) as f32 / 255.0, ((parsed >> 16) & 0xFF) as f32 / 255.0, ((parsed >> 8) & 0xFF) as f32 / 255.0, (parsed & 0xFF) as f32 / 255.0))
         } else {
-            panic!("invalid format!")
+            Err(Error::InvalidHex(hex.to_owned()))
         }
     }
 }
 
-impl<'p> Clone for Pixel<'p> {
-    fn clone(&self) -> Pixel<'p> {
-        let slice = self.slice();
-        let data = slice.to_owned();
-        Pixel { data: PixelData::Owned(data.into_boxed_slice()), format: self.format }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    U8,
+    U16,
+    F32
+}
+
+impl BitDepth {
+    pub fn of(image: &DynamicImage) -> BitDepth {
+        match image.color() {
+            ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => BitDepth::U8,
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => BitDepth::U16,
+            _ => BitDepth::F32
+        }
     }
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BorderMode {
+    Wrap,
+    Clamp,
+    Mirror,
+    Constant(Pixel<'static>),
+    Skip
+}
+
 #[derive(Clone)]
 pub struct FImage {
     width: usize,
     height: usize,
     format: PixelFormat,
+    border: BorderMode,
+    color_space: ColorSpace,
     pixels: Box<[f32]>
 }
 
+fn wrap_coord(c: i32, size: usize) -> usize {
+    c.rem_euclid(size as i32) as usize
+}
+
+fn mirror_coord(c: i32, size: usize) -> usize {
+    // reflects around the edge pixels without repeating them (dcb|abcd|cba)
+    if size == 1 {
+        return 0;
+    }
+
+    let period = 2 * (size as i32 - 1);
+    let r = c.rem_euclid(period);
+
+    (if r < size as i32 { r } else { period - r }) as usize
+}
+
 impl FImage {
     pub fn new(width: usize, height: usize, format: PixelFormat) -> FImage {
         let channels = format.channel_count();
         let data = vec![0.0; width * height * channels];
 
-        FImage { width, height, format, pixels: data.into_boxed_slice() }
+        FImage { width, height, format, border: BorderMode::Wrap, color_space: ColorSpace::Srgb, pixels: data.into_boxed_slice() }
     }
 
-    pub fn get_pixel(&self, x: i32, y: i32) -> Pixel {
-        let channels = self.format.channel_count();
-        
-        let mod_x = {let r = x % self.width as i32; if r < 0 {r + self.width as i32} else {r}} as usize;
-        let mod_y = {let r = y % self.height as i32; if r < 0 {r + self.height as i32} else {r}} as usize;
+    // blank image with the same dimensions, border mode and color space
+    pub fn new_like(&self, format: PixelFormat) -> FImage {
+        let mut out = FImage::new(self.width, self.height, format);
+        out.border = self.border.clone();
+        out.color_space = self.color_space;
 
-        let offset = channels as usize * (mod_x + mod_y * self.width);
+        out
+    }
 
-        Pixel::from_slice(&self.pixels[offset..offset + channels])
+    pub fn border_mode(&self) -> &BorderMode {
+        &self.border
     }
 
-    pub fn set_pixel(&mut self, x: i32, y: i32, pixel: Pixel) {
-        let channels = self.format.channel_count();
-        
-        let mod_x = {let r = x % self.width as i32; if r < 0 {r + self.width as i32} else {r}} as usize;
-        let mod_y = {let r = y % self.height as i32; if r < 0 {r + self.height as i32} else {r}} as usize;
+    pub fn set_border_mode(&mut self, mode: BorderMode) {
+        self.border = mode;
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    // only changes the tag, use convert_color_space to convert the pixel data
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    // maps a coordinate to the pixel it refers to under the current border mode,
+    // returns None if there is no such pixel (Constant and Skip outside the image, anything in an empty image)
+    pub fn resolve_coords(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        if self.in_bounds(x, y) {
+            return Some((x as usize, y as usize));
+        }
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        match self.border {
+            BorderMode::Wrap => Some((wrap_coord(x, self.width), wrap_coord(y, self.height))),
+            BorderMode::Clamp => Some((x.clamp(0, self.width as i32 - 1) as usize, y.clamp(0, self.height as i32 - 1) as usize)),
+            BorderMode::Mirror => Some((mirror_coord(x, self.width), mirror_coord(y, self.height))),
+            BorderMode::Constant(_) | BorderMode::Skip => None
+        }
+    }
 
-        let offset = channels as usize * (mod_x + mod_y * self.width);
+    fn border_pixel(&self) -> Pixel<'static> {
+        let color = match &self.border {
+            BorderMode::Constant(p) => [p.r(), p.g(), p.b(), p.a()],
+            _ => [0.0; 4]
+        };
 
-        self.pixels[offset] = pixel.r();
-        
-        if channels >= 3 {
-            self.pixels[offset + 1] = pixel.g();
-            self.pixels[offset + 2] = pixel.b();
+        Pixel::from_array(color, self.format)
+    }
+
+    // like get_pixel, but returns None for pixels that the border mode skips
+    pub fn get_pixel_checked(&self, x: i32, y: i32) -> Option<Pixel<'_>> {
+        match self.resolve_coords(x, y) {
+            Some((px, py)) => Some(Pixel::from_slice(self.pixel_slice(px, py))),
+            None if matches!(self.border, BorderMode::Constant(_)) => Some(self.border_pixel()),
+            None => None
         }
+    }
+
+    // skipped pixels read as zero, use get_pixel_checked to tell them apart
+    pub fn get_pixel(&self, x: i32, y: i32) -> Pixel<'_> {
+        self.get_pixel_checked(x, y).unwrap_or_else(|| self.border_pixel())
+    }
 
-        if channels == 4 {
-            self.pixels[offset + 3] = pixel.a();
+    // border modes only apply to reads, writes outside the image are dropped unless it wraps
+    pub(crate) fn write_coords(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        if self.in_bounds(x, y) {
+            Some((x as usize, y as usize))
+        } else if matches!(self.border, BorderMode::Wrap) && self.width > 0 && self.height > 0 {
+            Some((wrap_coord(x, self.width), wrap_coord(y, self.height)))
+        } else {
+            None
         }
     }
 
+    pub fn set_pixel(&mut self, x: i32, y: i32, pixel: Pixel) {
+        let channels = self.format.channel_count();
+
+        let (px, py) = match self.write_coords(x, y) {
+            Some(c) => c,
+            None => return
+        };
+
+        let offset = channels * (px + py * self.width);
+
+        pixel.write_to_slice(&mut self.pixels[offset..offset + channels]);
+    }
+
     pub fn set_pixel_blended(&mut self, x: i32, y: i32, pixel: Pixel) {
-        let current = self.get_pixel(x, y);
-        
-        let a = pixel.a();
-        let a_ = 1.0 - a;
+        self.set_pixel_composite(x, y, pixel, CompositeOp::Over);
+    }
 
-        let r = a * pixel.r() + a_ * current.a() * current.r();
-        let g = a * pixel.g() + a_ * current.a() * current.g();
-        let b = a * pixel.b() + a_ * current.a() * current.b();
-        let a = a + a_ * current.a();
+    // raw interleaved channel data, row major
+    pub fn data(&self) -> &[f32] {
+        &self.pixels
+    }
+
+    pub fn data_mut(&mut self) -> &mut [f32] {
+        &mut self.pixels
+    }
 
-        let new_pixel = Pixel::rgba(r, g, b, a);
+    // direct access to an in-bounds pixel, panics for coordinates outside the image
+    pub fn pixel_slice(&self, x: usize, y: usize) -> &[f32] {
+        let channels = self.format.channel_count();
+        let offset = channels * (x + y * self.width);
 
-        self.set_pixel(x, y, new_pixel);
+        &self.pixels[offset..offset + channels]
+    }
+
+    pub fn pixel_slice_mut(&mut self, x: usize, y: usize) -> &mut [f32] {
+        let channels = self.format.channel_count();
+        let offset = channels * (x + y * self.width);
+
+        &mut self.pixels[offset..offset + channels]
     }
 
     pub fn get_pixel_format(&self) -> PixelFormat {
@@ -206,22 +369,6 @@ impl FImage {
         self.height
     }
 
-    pub fn normalize(&mut self) {
-        let mut min = f32::INFINITY;
-        let mut max = f32::NEG_INFINITY;
-
-        for (i, c) in self.pixels.iter().enumerate() {
-            if !matches!(self.format, PixelFormat::RGBA) || i % 4 != 3 {
-                if *c > max {max = *c}
-                if *c < min {min = *c}
-            }
-        }
-
-        println!("min: {}, max: {}", min, max);
-
-        self.clip(min, max);
-    }
-
     pub fn clip(&mut self, min: f32, max: f32) {
         let mut range = max - min;
         if range == 0.0 {
@@ -234,45 +381,285 @@ impl FImage {
         }
     }
 
-    pub fn copy_from_image_buffer<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&mut self, image: &I) {
-        if image.width() as usize != self.width || image.height() as usize != self.height {
-            panic!("Dimensions do not match!");
+    fn check_dimensions(&self, width: u32, height: u32) -> Result<()> {
+        if width as usize != self.width || height as usize != self.height {
+            return Err(Error::DimensionMismatch { expected: (self.width, self.height), found: (width as usize, height as usize) });
         }
 
+        Ok(())
+    }
+
+    pub fn copy_from_image_buffer<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&mut self, image: &I) {
+        self.try_copy_from_image_buffer(image).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // integer subpixels are scaled from their full range to [0, 1], float subpixels are taken as-is
+    pub fn try_copy_from_image_buffer<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&mut self, image: &I) -> Result<()> {
+        self.check_dimensions(image.width(), image.height())?;
+
+        let (max, _) = subpixel_range::<SP>();
+
         for x in 0..self.width {
             for y in 0..self.height {
                 let t = image.get_pixel(x as u32, y as u32).to_rgba();
-                let p = Pixel::rgba(t.0[0].to_f32().unwrap() / 255.0, t.0[1].to_f32().unwrap() / 255.0, t.0[2].to_f32().unwrap() / 255.0, t.0[3].to_f32().unwrap() / 255.0);
+                let c = |i: usize| t.0[i].to_f32().unwrap_or(0.0) / max;
+                let p = Pixel::rgba(c(0), c(1), c(2), c(3));
 
                 self.set_pixel(x as i32, y as i32, p);
             }
         }
+
+        Ok(())
     }
 
     pub fn copy_to_image_buffer<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&self, image: &mut I) {
-        if image.width() as usize != self.width || image.height() as usize != self.height {
-            panic!("Dimensions do not match!");
-        }
+        self.try_copy_to_image_buffer(image).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        let mut temp = self.clone();
-        temp.clip(0.0, 1.0);
+    // integer subpixels are clipped to [0, 1] and rounded, float subpixels keep out of range values
+    pub fn try_copy_to_image_buffer<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&self, image: &mut I) -> Result<()> {
+        self.check_dimensions(image.width(), image.height())?;
+
+        let (max, is_float) = subpixel_range::<SP>();
+        let convert = |c: f32| {
+            let v = if is_float { c * max } else { (c.clamp(0.0, 1.0) * max).round() };
+            // max rounds up to 2^32 in f32 for u32 subpixels, so the top value saturates
+            SP::from(v).unwrap_or(if v > 0.0 { SP::DEFAULT_MAX_VALUE } else { SP::DEFAULT_MIN_VALUE })
+        };
 
         for x in 0..self.width {
             for y in 0..self.height {
-                let p = temp.get_pixel(x as i32, y as i32);
-                let p2 = Pixel::rgba(p.r(), p.g(), p.b(), p.a());
-
-                let mut v = vec![SP::DEFAULT_MAX_VALUE; P::CHANNEL_COUNT as usize];
-                let a = p2.slice();
+                let p = self.get_pixel(x as i32, y as i32);
+                // gray buffers get the luma of color images, mono images go through unchanged
+                let gray = if self.format == PixelFormat::Mono { p.r() } else { p.luma() };
 
-                for i in 0..v.len().min(a.len()) {
-                    v[i] = SP::from(a[i] * 255.0).unwrap();
-                }
+                let v: Vec<SP> = match P::CHANNEL_COUNT {
+                    1 => vec![convert(gray)],
+                    2 => vec![convert(gray), convert(p.a())],
+                    3 => vec![convert(p.r()), convert(p.g()), convert(p.b())],
+                    _ => vec![convert(p.r()), convert(p.g()), convert(p.b()), convert(p.a())]
+                };
 
-                let pixel = P::from_slice(&v).clone();
+                let pixel = *P::from_slice(&v);
 
                 image.put_pixel(x as u32, y as u32, pixel);
             }
         }
+
+        Ok(())
+    }
+
+    pub fn from_dynamic_image(image: &DynamicImage) -> FImage {
+        let format = match image.color() {
+            ColorType::L8 | ColorType::L16 => PixelFormat::Mono,
+            ColorType::Rgb8 | ColorType::Rgb16 | ColorType::Rgb32F => PixelFormat::RGB,
+            _ => PixelFormat::RGBA
+        };
+
+        let mut out = FImage::new(image.width() as usize, image.height() as usize, format);
+
+        // the dimensions always match here, so the copies can't fail
+        match image {
+            DynamicImage::ImageLuma8(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageLumaA8(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageRgb8(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageRgba8(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageLuma16(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageLumaA16(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageRgb16(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageRgba16(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageRgb32F(buf) => out.copy_from_image_buffer(buf),
+            DynamicImage::ImageRgba32F(buf) => out.copy_from_image_buffer(buf),
+            other => out.copy_from_image_buffer(&other.to_rgba32f())
+        }
+
+        out
+    }
+
+    pub fn to_dynamic_image(&self, depth: BitDepth) -> DynamicImage {
+        self.try_to_dynamic_image(depth).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // there is no float mono buffer type, Mono images have to be converted to RGB before exporting at F32
+    pub fn try_to_dynamic_image(&self, depth: BitDepth) -> Result<DynamicImage> {
+        let (w, h) = (self.width as u32, self.height as u32);
+
+        macro_rules! export {
+            ($variant:ident, $buffer:ty) => {{
+                let mut buf = <$buffer>::new(w, h);
+                self.copy_to_image_buffer(&mut buf);
+                Ok(DynamicImage::$variant(buf))
+            }};
+        }
+
+        match (self.format, depth) {
+            (PixelFormat::Mono, BitDepth::U8) => export!(ImageLuma8, GrayImage),
+            (PixelFormat::Mono, BitDepth::U16) => export!(ImageLuma16, ImageBuffer<Luma<u16>, Vec<u16>>),
+            (PixelFormat::RGB, BitDepth::U8) => export!(ImageRgb8, RgbImage),
+            (PixelFormat::RGB, BitDepth::U16) => export!(ImageRgb16, ImageBuffer<Rgb<u16>, Vec<u16>>),
+            (PixelFormat::Mono, BitDepth::F32) => Err(Error::UnsupportedPixelFormat { expected: PixelFormat::RGB, found: PixelFormat::Mono }),
+            (PixelFormat::RGB, BitDepth::F32) => export!(ImageRgb32F, Rgb32FImage),
+            (PixelFormat::RGBA, BitDepth::U8) => export!(ImageRgba8, RgbaImage),
+            (PixelFormat::RGBA, BitDepth::U16) => export!(ImageRgba16, ImageBuffer<Rgba<u16>, Vec<u16>>),
+            (PixelFormat::RGBA, BitDepth::F32) => export!(ImageRgba32F, Rgba32FImage)
+        }
+    }
+}
+
+// (value of a fully saturated subpixel, whether the subpixel is a float type)
+pub(crate) fn subpixel_range<SP: Primitive>() -> (f32, bool) {
+    let max = SP::DEFAULT_MAX_VALUE.to_f32().unwrap_or(1.0);
+    let is_float = SP::DEFAULT_MAX_VALUE < SP::max_value();
+
+    (max, is_float)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, DynamicImage, GrayImage, ImageBuffer, Rgba};
+
+    use super::{BitDepth, BorderMode, FImage, Pixel, PixelFormat};
+    use crate::error::Error;
+
+    fn ramp(width: usize) -> FImage {
+        let mut img = FImage::new(width, 1, PixelFormat::Mono);
+        for x in 0..width {
+            img.set_pixel(x as i32, 0, Pixel::mono(x as f32));
+        }
+
+        img
+    }
+
+    fn values(img: &FImage) -> Vec<f32> {
+        (0..img.width() as i32).map(|x| img.get_pixel(x, 0).r()).collect()
+    }
+
+    #[test]
+    fn border_modes_resolve_outside_reads() {
+        let mut img = ramp(4);
+        let row = |img: &FImage| (-3..7).map(|x| img.get_pixel_checked(x, 0).map(|p| p.r())).collect::<Vec<_>>();
+
+        img.set_border_mode(BorderMode::Wrap);
+        assert_eq!(row(&img), [1.0, 2.0, 3.0, 0.0, 1.0, 2.0, 3.0, 0.0, 1.0, 2.0].map(Some));
+        img.set_border_mode(BorderMode::Clamp);
+        assert_eq!(row(&img), [0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 3.0, 3.0, 3.0].map(Some));
+        img.set_border_mode(BorderMode::Mirror);
+        assert_eq!(row(&img), [3.0, 2.0, 1.0, 0.0, 1.0, 2.0, 3.0, 2.0, 1.0, 0.0].map(Some));
+        img.set_border_mode(BorderMode::Constant(Pixel::mono(9.0)));
+        assert_eq!(row(&img), [9.0, 9.0, 9.0, 0.0, 1.0, 2.0, 3.0, 9.0, 9.0, 9.0].map(Some));
+        img.set_border_mode(BorderMode::Skip);
+        assert_eq!(row(&img), [None, None, None, Some(0.0), Some(1.0), Some(2.0), Some(3.0), None, None, None]);
+        assert_eq!(img.get_pixel(-1, 0).r(), 0.0);
+    }
+
+    #[test]
+    fn writes_outside_only_land_when_wrapping() {
+        let mut img = ramp(4);
+        img.set_pixel(-1, 0, Pixel::mono(7.0));
+        img.set_pixel_blended(5, 0, Pixel::rgba(6.0, 6.0, 6.0, 1.0));
+        assert_eq!(values(&img), [0.0, 6.0, 2.0, 7.0]);
+
+        for mode in [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Constant(Pixel::mono(9.0)), BorderMode::Skip] {
+            img.set_border_mode(mode);
+            img.set_pixel(-1, 0, Pixel::mono(5.0));
+            img.set_pixel(4, 0, Pixel::mono(5.0));
+            img.set_pixel_blended(0, -2, Pixel::rgba(5.0, 5.0, 5.0, 1.0));
+            assert_eq!(values(&img), [0.0, 6.0, 2.0, 7.0]);
+        }
+    }
+
+    #[test]
+    fn empty_images_have_nothing_to_resolve() {
+        for mode in [BorderMode::Wrap, BorderMode::Clamp, BorderMode::Mirror, BorderMode::Skip] {
+            for (w, h) in [(0, 0), (0, 3), (3, 0)] {
+                let mut img = FImage::new(w, h, PixelFormat::RGB);
+                img.set_border_mode(mode.clone());
+
+                assert_eq!(img.resolve_coords(1, 1), None);
+                assert!(img.get_pixel_checked(-1, 0).is_none());
+                img.set_pixel(0, 0, Pixel::rgb(1.0, 1.0, 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_and_float_images_round_trip() {
+        let mut buf = ImageBuffer::<Rgba<u16>, Vec<u16>>::new(3, 2);
+        for (i, p) in buf.pixels_mut().enumerate() {
+            *p = Rgba([i as u16 * 9000, 65535 - i as u16, 257, 40000]);
+        }
+        let img = FImage::from_dynamic_image(&DynamicImage::ImageRgba16(buf.clone()));
+        assert_eq!(img.get_pixel_format(), PixelFormat::RGBA);
+        assert_eq!(img.get_pixel(1, 0).slice(), &[9000.0 / 65535.0, 65534.0 / 65535.0, 257.0 / 65535.0, 40000.0 / 65535.0]);
+        assert_eq!(img.to_dynamic_image(BitDepth::U16).into_rgba16(), buf);
+
+        let values = [[-1.0, 0.5, 7.25], [0.0, 1.0, 2.0], [3.0, 4.0, 5.0], [1e-6, 1e6, 0.1]];
+        let mut hdr = FImage::new(2, 2, PixelFormat::RGB);
+        for (i, [r, g, b]) in values.into_iter().enumerate() {
+            hdr.set_pixel(i as i32 % 2, i as i32 / 2, Pixel::rgb(r, g, b));
+        }
+        let back = FImage::from_dynamic_image(&hdr.to_dynamic_image(BitDepth::F32));
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(back.get_pixel(i as i32 % 2, i as i32 / 2).slice(), v);
+        }
+    }
+
+    #[test]
+    fn mono_float_export_is_rejected() {
+        let mono = FImage::new(2, 2, PixelFormat::Mono);
+        assert!(matches!(mono.try_to_dynamic_image(BitDepth::F32), Err(Error::UnsupportedPixelFormat { expected: PixelFormat::RGB, found: PixelFormat::Mono })));
+        assert_eq!(mono.to_dynamic_image(BitDepth::U16).color(), ColorType::L16);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn u32_subpixels_reach_their_maximum() {
+        let mut img = FImage::new(2, 1, PixelFormat::Mono);
+        img.set_pixel(1, 0, Pixel::mono(1.0));
+        let mut buf = ImageBuffer::<image::Luma<u32>, Vec<u32>>::new(2, 1);
+        img.copy_to_image_buffer(&mut buf);
+        assert_eq!(buf.into_raw(), [0, u32::MAX]);
+    }
+
+    #[test]
+    fn gray_export_uses_luma() {
+        let mut img = FImage::new(1, 1, PixelFormat::RGB);
+        img.set_pixel(0, 0, Pixel::rgb(0.0, 1.0, 0.0));
+        let mut gray = GrayImage::new(1, 1);
+        img.copy_to_image_buffer(&mut gray);
+        assert_eq!(gray.get_pixel(0, 0).0[0], (0.7152f32 * 255.0).round() as u8);
+
+        let mut mono = FImage::new(1, 1, PixelFormat::Mono);
+        mono.set_pixel(0, 0, Pixel::mono(0.5));
+        assert_eq!(mono.to_dynamic_image(BitDepth::U8).into_luma8().get_pixel(0, 0).0[0], 128);
+    }
+
+    #[test]
+    fn pixels_are_inline_values() {
+        fn copy<T: Copy>(t: T) -> T { t }
+
+        // no heap data behind an owned pixel, it is plain inline storage
+        assert!(std::mem::size_of::<Pixel>() <= 32);
+        let p = copy(Pixel::rgb(0.1, 0.2, 0.3));
+        assert_eq!(p.slice(), &[0.1, 0.2, 0.3]);
+        assert_eq!(p.a(), 1.0);
+        assert_eq!(Pixel::mono(0.4).b(), 0.4);
+
+        let mut img = FImage::new(2, 2, PixelFormat::RGBA);
+        img.pixel_slice_mut(1, 1).copy_from_slice(&[0.1, 0.2, 0.3, 0.4]);
+        let owned = img.get_pixel(1, 1).to_owned_pixel();
+        img.data_mut().fill(0.0);
+        assert_eq!(owned.slice(), &[0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(img.pixel_slice(1, 1), &[0.0; 4]);
+    }
+
+    #[test]
+    fn pixels_convert_between_formats_on_write() {
+        let mut rgba = FImage::new(1, 1, PixelFormat::RGBA);
+        rgba.set_pixel(0, 0, Pixel::rgb(0.1, 0.2, 0.3));
+        assert_eq!(rgba.pixel_slice(0, 0), &[0.1, 0.2, 0.3, 1.0]);
+
+        let mut rgb = FImage::new(1, 1, PixelFormat::RGB);
+        rgb.set_pixel(0, 0, Pixel::mono(0.5));
+        assert_eq!(rgb.pixel_slice(0, 0), &[0.5; 3]);
+    }
+}
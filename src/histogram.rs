@@ -0,0 +1,343 @@
+use crate::error::{Error, Result};
+use crate::float_image::{FImage, PixelFormat};
+
+// equal width bins over [min, max], values outside the range are counted in the first or last bin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    min: f32,
+    max: f32
+}
+
+// cumulative fraction of the total at each bin edge, so bins + 1 entries running from 0 to 1
+fn edge_cdf(counts: &[f32]) -> Vec<f32> {
+    let total: f32 = counts.iter().sum();
+    let mut edges = Vec::with_capacity(counts.len() + 1);
+    let mut sum = 0.0;
+
+    edges.push(0.0);
+    for c in counts {
+        sum += c;
+        edges.push(if total > 0.0 { sum / total } else { 0.0 });
+    }
+
+    edges
+}
+
+// fraction of values below v, interpolated linearly inside the bin
+fn lookup_cdf(edges: &[f32], min: f32, max: f32, v: f32) -> f32 {
+    let bins = edges.len() - 1;
+    let width = (max - min) / bins as f32;
+    if width <= 0.0 {
+        return if v < min { 0.0 } else { edges[bins] };
+    }
+
+    let t = ((v - min) / width).clamp(0.0, bins as f32);
+    let b = (t as usize).min(bins - 1);
+
+    edges[b] + (t - b as f32) * (edges[b + 1] - edges[b])
+}
+
+// inverse of lookup_cdf
+fn lookup_quantile(edges: &[f32], min: f32, max: f32, q: f32) -> f32 {
+    let bins = edges.len() - 1;
+    let q = q.clamp(0.0, 1.0);
+
+    let b = edges[1..].partition_point(|&e| e < q).min(bins - 1);
+    let span = edges[b + 1] - edges[b];
+    let frac = if span > 0.0 { ((q - edges[b]) / span).clamp(0.0, 1.0) } else { 0.0 };
+
+    min + (b as f32 + frac) * (max - min) / bins as f32
+}
+
+impl Histogram {
+    pub fn new(bins: usize, min: f32, max: f32) -> Histogram {
+        Histogram { counts: vec![0; bins.max(1)], min, max }
+    }
+
+    pub fn from_values<I: IntoIterator<Item = f32>>(values: I, bins: usize, min: f32, max: f32) -> Histogram {
+        let mut hist = Histogram::new(bins, min, max);
+        for v in values {
+            hist.add(v);
+        }
+
+        hist
+    }
+
+    // NaNs are not counted
+    pub fn add(&mut self, value: f32) {
+        if !value.is_nan() {
+            let bin = self.bin_of(value);
+            self.counts[bin] += 1;
+        }
+    }
+
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn bins(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        (self.min, self.max)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn bin_of(&self, value: f32) -> usize {
+        let t = (value - self.min) / (self.max - self.min) * self.bins() as f32;
+
+        if t.is_nan() { 0 } else { (t.max(0.0) as usize).min(self.bins() - 1) }
+    }
+
+    pub fn bin_center(&self, bin: usize) -> f32 {
+        self.min + (bin as f32 + 0.5) * (self.max - self.min) / self.bins() as f32
+    }
+
+    fn edges(&self) -> Vec<f32> {
+        edge_cdf(&self.counts.iter().map(|&c| c as f32).collect::<Vec<_>>())
+    }
+
+    // fraction of the values below value
+    pub fn cdf(&self, value: f32) -> f32 {
+        lookup_cdf(&self.edges(), self.min, self.max, value)
+    }
+
+    // value below which a fraction q of the values lie, so quantile(0.5) is the median
+    pub fn quantile(&self, q: f32) -> f32 {
+        lookup_quantile(&self.edges(), self.min, self.max, q)
+    }
+}
+
+// (min, max) of the non NaN values, (0, 0) if there are none
+pub(crate) fn value_range<'a, I: IntoIterator<Item = &'a f32>>(values: I) -> (f32, f32) {
+    let (min, max) = values.into_iter().filter(|v| !v.is_nan())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+
+    if min > max { (0.0, 0.0) } else { (min, max) }
+}
+
+impl FImage {
+    pub fn histogram(&self, channel: usize, bins: usize, min: f32, max: f32) -> Histogram {
+        self.try_histogram(channel, bins, min, max).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_histogram(&self, channel: usize, bins: usize, min: f32, max: f32) -> Result<Histogram> {
+        let channels = self.get_pixel_format().channel_count();
+        if channel >= channels {
+            return Err(Error::InvalidChannel { channel, format: self.get_pixel_format() });
+        }
+
+        Ok(Histogram::from_values(self.data().iter().skip(channel).step_by(channels).copied(), bins, min, max))
+    }
+
+    // one histogram per channel, alpha included
+    pub fn channel_histograms(&self, bins: usize, min: f32, max: f32) -> Vec<Histogram> {
+        (0..self.get_pixel_format().channel_count()).map(|c| self.histogram(c, bins, min, max)).collect()
+    }
+
+    pub fn luma_histogram(&self, bins: usize, min: f32, max: f32) -> Histogram {
+        Histogram::from_values(self.pixels().map(|p| p.luma()), bins, min, max)
+    }
+
+    // Runs func on the intensity of the image: mono images directly, color images on their luma. The color
+    // channels are then scaled by the change in luma, which keeps their ratios and so the hue intact (black
+    // has no ratios and is shifted instead). Alpha and the color space are preserved.
+    fn map_intensity<F: FnOnce(&mut FImage)>(&mut self, func: F) {
+        if self.get_pixel_format() == PixelFormat::Mono {
+            func(self);
+            return;
+        }
+
+        let mut luma = self.new_like(PixelFormat::Mono);
+        for (dst, p) in luma.data_mut().iter_mut().zip(self.pixels()) {
+            *dst = p.luma();
+        }
+        let original = luma.clone();
+
+        func(&mut luma);
+
+        let channels = self.get_pixel_format().channel_count();
+        for ((dst, new), old) in self.data_mut().chunks_exact_mut(channels).zip(luma.data()).zip(original.data()) {
+            if *old > 0.0 {
+                dst[..3].iter_mut().for_each(|v| *v *= new / old);
+            } else {
+                dst[..3].iter_mut().for_each(|v| *v += new - old);
+            }
+        }
+    }
+
+    // spreads the intensities evenly over [0, 1]
+    pub fn equalize(&mut self, bins: usize) {
+        self.map_intensity(|img| {
+            let (min, max) = value_range(img.data());
+            let hist = Histogram::from_values(img.data().iter().copied(), bins, min, max);
+            let edges = hist.edges();
+
+            for v in img.data_mut() {
+                *v = lookup_cdf(&edges, min, max, *v);
+            }
+        });
+    }
+
+    // Remaps every color channel so its distribution follows the same channel of reference. A mono reference
+    // is used for every channel and a mono image follows the reference's luma. Alpha is left alone.
+    pub fn match_histogram(&mut self, reference: &FImage, bins: usize) {
+        let channels = self.get_pixel_format().channel_count();
+        let color = channels.min(3);
+
+        let reference_values: Vec<Vec<f32>> = (0..color).map(|c| match (self.get_pixel_format(), reference.get_pixel_format()) {
+            (_, PixelFormat::Mono) => reference.data().to_vec(),
+            (PixelFormat::Mono, _) => reference.pixels().map(|p| p.luma()).collect(),
+            _ => reference.pixels().map(|p| p.slice()[c]).collect()
+        }).collect();
+
+        for (c, ref_values) in reference_values.iter().enumerate() {
+            let (src_min, src_max) = value_range(self.data().iter().skip(c).step_by(channels));
+            let src_edges = Histogram::from_values(self.data().iter().skip(c).step_by(channels).copied(), bins, src_min, src_max).edges();
+
+            let (ref_min, ref_max) = value_range(ref_values);
+            let ref_edges = Histogram::from_values(ref_values.iter().copied(), bins, ref_min, ref_max).edges();
+
+            for v in self.data_mut().iter_mut().skip(c).step_by(channels) {
+                *v = lookup_quantile(&ref_edges, ref_min, ref_max, lookup_cdf(&src_edges, src_min, src_max, *v));
+            }
+        }
+    }
+
+    // Contrast limited adaptive histogram equalization. Every tile of the grid gets its own equalization
+    // with the bins clipped at clip_limit times the average bin height, pixels blend the mappings of the
+    // four nearest tiles.
+    pub fn clahe(&mut self, tiles_x: usize, tiles_y: usize, clip_limit: f32, bins: usize) {
+        let bins = bins.max(1);
+
+        self.map_intensity(|img| {
+            let (w, h) = (img.width(), img.height());
+            if w == 0 || h == 0 {
+                return;
+            }
+
+            let tiles_x = tiles_x.clamp(1, w);
+            let tiles_y = tiles_y.clamp(1, h);
+            let (min, max) = value_range(img.data());
+
+            let mut tables = Vec::with_capacity(tiles_x * tiles_y);
+            for ty in 0..tiles_y {
+                for tx in 0..tiles_x {
+                    let (x0, x1) = (tx * w / tiles_x, (tx + 1) * w / tiles_x);
+                    let (y0, y1) = (ty * h / tiles_y, (ty + 1) * h / tiles_y);
+
+                    let values = (y0..y1).flat_map(|y| img.data()[y * w + x0..y * w + x1].iter().copied());
+                    let hist = Histogram::from_values(values, bins, min, max);
+                    let mut counts: Vec<f32> = hist.counts().iter().map(|&c| c as f32).collect();
+
+                    // clip and hand the excess back out evenly
+                    let limit = (clip_limit * ((x1 - x0) * (y1 - y0)) as f32 / bins as f32).max(1.0);
+                    let excess: f32 = counts.iter().map(|c| (c - limit).max(0.0)).sum();
+                    for c in counts.iter_mut() {
+                        *c = c.min(limit) + excess / bins as f32;
+                    }
+
+                    tables.push(edge_cdf(&counts));
+                }
+            }
+
+            let tile_w = w as f32 / tiles_x as f32;
+            let tile_h = h as f32 / tiles_y as f32;
+            // neighbouring tile indices and blend factor along one axis
+            let neighbours = |p: usize, size: f32, count: usize| {
+                let g = (p as f32 + 0.5) / size - 0.5;
+                let t0 = (g.max(0.0) as usize).min(count - 1);
+                let t1 = (t0 + 1).min(count - 1);
+
+                (t0, t1, (g - t0 as f32).clamp(0.0, 1.0))
+            };
+
+            for (x, y, px) in img.enumerate_pixels_mut() {
+                let (tx0, tx1, fx) = neighbours(x, tile_w, tiles_x);
+                let (ty0, ty1, fy) = neighbours(y, tile_h, tiles_y);
+                let map = |tx: usize, ty: usize| lookup_cdf(&tables[tx + ty * tiles_x], min, max, px[0]);
+
+                let top = map(tx0, ty0) * (1.0 - fx) + map(tx1, ty0) * fx;
+                let bottom = map(tx0, ty1) * (1.0 - fx) + map(tx1, ty1) * fx;
+                px[0] = top * (1.0 - fy) + bottom * fy;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Histogram;
+    use crate::color_space::ColorSpace;
+    use crate::float_image::{FImage, PixelFormat};
+
+    fn ramp(w: usize, h: usize, format: PixelFormat, func: fn(f32) -> f32) -> FImage {
+        let mut img = FImage::new(w, h, format);
+        let channels = format.channel_count();
+        for (i, px) in img.data_mut().chunks_exact_mut(channels).enumerate() {
+            let v = func(i as f32 / (w * h - 1) as f32);
+            px.iter_mut().enumerate().for_each(|(c, p)| *p = if c == 3 { 0.5 } else { v * (1.0 - 0.2 * c as f32) });
+        }
+
+        img
+    }
+
+    #[test]
+    fn counting_and_quantiles() {
+        let hist = Histogram::from_values([0.05, 0.15, 0.15, 0.95, -3.0, 7.0, f32::NAN], 10, 0.0, 1.0);
+        assert_eq!(hist.counts(), &[2, 2, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(hist.total(), 6);
+        assert!((hist.bin_center(1) - 0.15).abs() < 1e-6);
+
+        let hist = Histogram::from_values((0..1000).map(|i| i as f32 / 1000.0), 100, 0.0, 1.0);
+        assert!((hist.quantile(0.5) - 0.5).abs() < 1e-3);
+        assert!((hist.cdf(0.25) - 0.25).abs() < 1e-3);
+        assert_eq!((hist.quantile(0.0), hist.quantile(1.0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn equalize_flattens_the_distribution() {
+        // values bunched up near 0
+        let mut img = ramp(64, 16, PixelFormat::Mono, |t| t * t * t);
+        img.equalize(256);
+
+        let hist = img.histogram(0, 4, 0.0, 1.0);
+        assert!(hist.counts().iter().all(|&c| (c as i64 - 256).abs() <= 16), "{:?}", hist.counts());
+    }
+
+    #[test]
+    fn color_images_keep_hue_alpha_and_tag() {
+        let mut img = ramp(32, 8, PixelFormat::RGBA, |t| 0.2 + 0.3 * t);
+        img.set_color_space(ColorSpace::LinearSrgb);
+        let before = img.clone();
+        img.clahe(4, 2, 2.0, 64);
+
+        assert_eq!(img.color_space(), ColorSpace::LinearSrgb);
+        assert!(img.data().iter().skip(3).step_by(4).all(|&a| a == 0.5));
+        // brighter, while the channel ratios stay close
+        let (a, b) = (before.get_pixel(30, 6), img.get_pixel(30, 6));
+        assert!(b.luma() > a.luma());
+        assert!((b.b() / b.r() - a.b() / a.r()).abs() < 0.1, "{:?} {:?}", a.slice(), b.slice());
+    }
+
+    #[test]
+    fn matching_a_histogram() {
+        let mut img = ramp(32, 32, PixelFormat::RGB, |t| t);
+        let reference = ramp(32, 32, PixelFormat::RGB, |t| 0.5 + 0.25 * t);
+        img.match_histogram(&reference, 256);
+
+        for c in 0..3 {
+            let (src, dst) = (img.histogram(c, 64, 0.0, 1.0).quantile(0.5), reference.histogram(c, 64, 0.0, 1.0).quantile(0.5));
+            assert!((src - dst).abs() < 2e-2, "channel {}: {} vs {}", c, src, dst);
+        }
+
+        let values: Vec<f32> = img.data().iter().step_by(3).copied().collect();
+        assert!(values.windows(2).all(|w| w[0] <= w[1] + 1e-6));
+        assert!(values[0] >= 0.49 && values[values.len() - 1] <= 0.76);
+    }
+}
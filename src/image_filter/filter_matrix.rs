@@ -1,15 +1,112 @@
+use crate::error::{Error, Result};
+
+use super::Kernel1D;
+
+// largest entry of M - col * row, relative to the largest entry of M, for a matrix to count as separable.
+// Tight enough that the two 1-D passes match the 2-D filter within float rounding.
+const SEPARABLE_TOLERANCE: f32 = 1e-5;
+
+// Largest power of ten step, down to 1e-6, that every entry is a multiple of, if it is fine compared to the
+// largest entry. Hand written tables like a gaussian to 4 decimals are only separable up to that rounding.
+fn quantization_step(mat: &[f32], max: f32) -> Option<f32> {
+    (0..=6).map(|k| 10f64.powi(-k)).find(|&step| {
+        mat.iter().all(|&v| {
+            let q = v as f64 / step;
+            (q - q.round()).abs() < 1e-3
+        })
+    }).map(|step| step as f32).filter(|&step| step <= max / 100.0)
+}
+
 pub struct FilterMatrix {
     dim: usize,
-    mat: Box<[f32]>
+    mat: Box<[f32]>,
+    // (row, col) when the matrix is their outer product, filter_image then runs two 1-D passes
+    separable: Option<(Kernel1D, Kernel1D)>
+}
+
+// Best rank 1 approximation sigma u v^T from the leading singular vectors, found by power iteration.
+// Returns the (row, col) factors if it is within tolerance of the matrix.
+fn factorize(mat: &[f32], dim: usize, tolerance: f32) -> Option<(Kernel1D, Kernel1D)> {
+    let max = mat.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    if max == 0.0 || !max.is_finite() {
+        return None;
+    }
+
+    let m = |y: usize, x: usize| mat[x + y * dim] as f64;
+    let normalize = |v: &mut [f64]| {
+        let len = v.iter().map(|a| a * a).sum::<f64>().sqrt();
+        v.iter_mut().for_each(|a| *a /= len);
+        len
+    };
+
+    // start from the strongest row so the start isn't orthogonal to the leading singular vector
+    let start = (0..dim).max_by(|&a, &b| {
+        let norm = |y: usize| (0..dim).map(|x| m(y, x).powi(2)).sum::<f64>();
+        norm(a).total_cmp(&norm(b))
+    }).unwrap();
+    let mut v: Vec<f64> = (0..dim).map(|x| m(start, x)).collect();
+    normalize(&mut v);
+
+    let mut u = vec![0.0; dim];
+    let mut sigma = 0.0;
+    for _ in 0..64 {
+        for (y, a) in u.iter_mut().enumerate() {
+            *a = (0..dim).map(|x| m(y, x) * v[x]).sum();
+        }
+        normalize(&mut u);
+
+        let previous = v.clone();
+        for (x, a) in v.iter_mut().enumerate() {
+            *a = (0..dim).map(|y| m(y, x) * u[y]).sum();
+        }
+        sigma = normalize(&mut v);
+
+        if v.iter().zip(&previous).all(|(a, b)| (a - b).abs() < 1e-12) {
+            break;
+        }
+    }
+
+    // split sigma evenly and keep the row's strongest tap positive
+    let strongest = v.iter().fold(0.0f64, |s, &a| if a.abs() > s.abs() { a } else { s });
+    let scale = sigma.sqrt() * strongest.signum();
+    let row: Vec<f32> = v.iter().map(|a| (a * scale) as f32).collect();
+    let col: Vec<f32> = u.iter().map(|a| (a * scale) as f32).collect();
+
+    let error = (0..dim * dim).fold(0.0f64, |e, i| {
+        e.max((m(i / dim, i % dim) - col[i / dim] as f64 * row[i % dim] as f64).abs())
+    });
+    if error > (tolerance * max) as f64 {
+        return None;
+    }
+
+    Some((Kernel1D::new(&row), Kernel1D::new(&col)))
+}
+
+// Tolerance for the automatic detection. A matrix whose entries are rounded to a step may be off by one step
+// per entry, the two passes then differ from the 2-D filter by at most dim^2 * step * the largest input value,
+// which is the same order as the rounding already in the table.
+fn detection_tolerance(mat: &[f32]) -> f32 {
+    let max = mat.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    match quantization_step(mat, max) {
+        Some(step) => SEPARABLE_TOLERANCE.max(step / max),
+        None => SEPARABLE_TOLERANCE
+    }
 }
 
 impl FilterMatrix {
     pub fn new<const N: usize>(matrix: [[f32; N]; N]) -> FilterMatrix {
-        if N % 2 == 0 {
-            panic!("FilterMatrix must have odd dimensions.");
+        FilterMatrix::try_new(matrix).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new<const N: usize>(matrix: [[f32; N]; N]) -> Result<FilterMatrix> {
+        if N.is_multiple_of(2) {
+            return Err(Error::EvenFilterSize(N));
         }
 
-        FilterMatrix { dim: N, mat: matrix.into_iter().flat_map(|arr| arr.into_iter()).collect() }
+        let mat: Box<[f32]> = matrix.into_iter().flat_map(|arr| arr.into_iter()).collect();
+        let separable = factorize(&mat, N, detection_tolerance(&mat));
+
+        Ok(FilterMatrix { dim: N, mat, separable })
     }
 
     pub fn get(&self, x: usize, y: usize) -> f32 {
@@ -19,4 +116,24 @@ impl FilterMatrix {
     pub fn size(&self) -> usize {
         self.dim
     }
-}
\ No newline at end of file
+
+    // whether the matrix is an outer product of two 1-D kernels, filter_image is then O(n) per pixel instead of O(n^2)
+    pub fn is_separable(&self) -> bool {
+        self.separable.is_some()
+    }
+
+    // Row and column kernels whose outer product is within tolerance * the largest entry of the matrix,
+    // for use with filter_image_separable. A looser tolerance than the automatic detection lets nearly
+    // separable matrices take the fast path too.
+    pub fn to_separable(&self, tolerance: f32) -> Option<(Kernel1D, Kernel1D)> {
+        factorize(&self.mat, self.dim, tolerance)
+    }
+
+    pub(crate) fn factors(&self) -> Option<&(Kernel1D, Kernel1D)> {
+        self.separable.as_ref()
+    }
+
+    pub(crate) fn sum(&self) -> f32 {
+        self.mat.iter().sum()
+    }
+}
@@ -0,0 +1,50 @@
+use crate::error::{Error, Result};
+
+// 1-D filter kernel, one row or column of a separable filter. Tap i sits at offset i - size / 2.
+#[derive(Debug, Clone)]
+pub struct Kernel1D {
+    weights: Box<[f32]>
+}
+
+impl Kernel1D {
+    pub fn new(weights: &[f32]) -> Kernel1D {
+        Kernel1D::try_new(weights).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(weights: &[f32]) -> Result<Kernel1D> {
+        if weights.len().is_multiple_of(2) {
+            return Err(Error::EvenFilterSize(weights.len()));
+        }
+
+        Ok(Kernel1D { weights: weights.into() })
+    }
+
+    // normalized gaussian reaching out to 3 sigma, a single tap for sigma <= 0
+    pub fn gaussian(sigma: f32) -> Kernel1D {
+        if sigma <= 0.0 || !sigma.is_finite() {
+            return Kernel1D { weights: Box::new([1.0]) };
+        }
+
+        let radius = (3.0 * sigma).ceil() as i32;
+        let weights: Vec<f32> = (-radius..=radius).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+        let total: f32 = weights.iter().sum();
+
+        Kernel1D { weights: weights.iter().map(|w| w / total).collect() }
+    }
+
+    pub fn get(&self, i: usize) -> f32 {
+        self.weights[i]
+    }
+
+    pub fn size(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    pub(crate) fn sum(&self) -> f32 {
+        self.weights.iter().sum()
+    }
+}
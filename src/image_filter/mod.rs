@@ -1,51 +1,175 @@
 mod filter_matrix;
+mod kernel;
 use std::collections::HashMap;
 
 pub use filter_matrix::FilterMatrix;
+pub use kernel::Kernel1D;
 
+use crate::error::{Error, Result};
 use crate::float_image::{Pixel, FImage, PixelFormat};
+use crate::image_view::GenericFImage;
 use priority_queue::PriorityQueue;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
-fn filter_pixel<'a>(pixel: (Pixel, i32, i32), img: &'a FImage, filter: &FilterMatrix) -> Pixel<'a> {
-    let a = pixel.0.a();
+fn filter_pixel<I: GenericFImage + ?Sized>(x: i32, y: i32, img: &I, filter: &FilterMatrix) -> [f32; 4] {
+    let a = img.get_pixel(x, y).a();
 
-    let mut sum = vec![0.0f32; pixel.0.format().channel_count()];
-    if sum.len() == 4 {
-        sum[3] = a;
-    }
+    let mut acc = [0.0f32; 4];
+    let mut weight_total = 0.0;
+    let mut weight_used = 0.0;
 
     let range = filter.size()  as i32 / 2;
-    for x in -range..=range {
-        for y in -range..=range {
-            let px = img.get_pixel(pixel.1 + x, pixel.2 + y);
+
+    // kernels that lie completely inside the image can skip the border handling
+    let inside = img.in_bounds(x - range, y - range) && img.in_bounds(x + range, y + range);
+
+    for dx in -range..=range {
+        for dy in -range..=range {
+            let weight = filter.get((dx + range) as usize, (dy + range) as usize);
+            weight_total += weight;
+
+            // pixels skipped by the border mode don't contribute
+            let px = if inside {
+                Pixel::from_slice(img.pixel_slice((x + dx) as usize, (y + dy) as usize))
+            } else {
+                match img.get_pixel_checked(x + dx, y + dy) {
+                    Some(px) => px,
+                    None => continue
+                }
+            };
+            weight_used += weight;
 
             for (i, sp) in px.slice().iter().enumerate() {
-                let t = *sp * filter.get((x + range) as usize, (y + range) as usize);
+                let t = *sp * weight;
             
-                sum[i] += t;
+                acc[i] += t;
             }
         }
     }
 
-    Pixel::from_boxed_slice(sum.into_boxed_slice())
+    // rescale so skipped border pixels don't darken the result, unless the kernel sums to zero (e.g. gradients)
+    let scale = if weight_used != 0.0 && weight_total != 0.0 { weight_total / weight_used } else { 1.0 };
+
+    let mut sum = acc.map(|v| v * scale);
+    if img.get_pixel_format() == PixelFormat::RGBA {
+        sum[3] += a;
+    }
+
+    sum
 }
 
-pub fn filter_image(img: &FImage, filter: FilterMatrix) -> FImage {
-    let mut out = FImage::new(img.width(), img.height(), img.get_pixel_format());
-    
-    for x in 0..img.width() {
-        for y in 0..img.height() {
-            let px = img.get_pixel(x as i32, y as i32);
+// Runs func(y, row) on every row of out. With the parallel feature the rows are spread
+// across threads; every pixel is still computed the same way, so the output is identical.
+fn for_each_row<F: Fn(usize, &mut [f32]) + Sync + Send>(out: &mut FImage, func: F) {
+    let stride = out.width() * out.get_pixel_format().channel_count();
+
+    for_each_chunk(out.data_mut(), stride, func);
+}
+
+// for_each_row over any buffer of rows `stride` values long
+fn for_each_chunk<F: Fn(usize, &mut [f32]) + Sync + Send>(data: &mut [f32], stride: usize, func: F) {
+    let stride = stride.max(1);
+
+    #[cfg(feature = "parallel")]
+    data.par_chunks_mut(stride).enumerate().for_each(|(y, row)| func(y, row));
+
+    #[cfg(not(feature = "parallel"))]
+    data.chunks_mut(stride).enumerate().for_each(|(y, row)| func(y, row));
+}
+
+// Filters with the matrix col * row as two 1-D passes. Borders and alpha are handled exactly like the
+// 2-D filter_pixel: skipped taps drop out of both passes and the result is rescaled by the weight that
+// was used, unless `total` (the summed weights) is zero. RGBA keeps adding the original alpha.
+fn separable_filter<I: GenericFImage + ?Sized>(img: &I, row: &Kernel1D, col: &Kernel1D, total: f32) -> FImage {
+    let format = img.get_pixel_format();
+    let channels = format.channel_count();
+    // every intermediate value carries the row weight its taps used as an extra channel
+    let stride = channels + 1;
+    let (width, rx, ry) = (img.width(), row.size() / 2, col.size() / 2);
+
+    let mut out = img.new_like(format);
+    if img.width() == 0 || img.height() == 0 {
+        return out;
+    }
+
+    // horizontal pass over every row the vertical pass reads, ry border rows above and below included
+    let mut rows = vec![0.0f32; width * (img.height() + 2 * ry) * stride];
+    for_each_chunk(&mut rows, width * stride, |i, line| {
+        let y = i as i32 - ry as i32;
+
+        for (x, px) in line.chunks_exact_mut(stride).enumerate() {
+            for k in 0..row.size() {
+                let weight = row.get(k);
+
+                if let Some(tap) = img.get_pixel_checked(x as i32 + k as i32 - rx as i32, y) {
+                    for (v, s) in px.iter_mut().zip(tap.slice()) {
+                        *v += s * weight;
+                    }
+                    px[channels] += weight;
+                }
+            }
+        }
+    });
+
+    for_each_row(&mut out, |y, line| {
+        for (x, px) in line.chunks_exact_mut(channels).enumerate() {
+            let mut acc = [0.0f32; 5];
+
+            for k in 0..col.size() {
+                let weight = col.get(k);
+                let start = ((y + k) * width + x) * stride;
+
+                for (a, v) in acc.iter_mut().zip(&rows[start..start + stride]) {
+                    *a += v * weight;
+                }
+            }
 
-            out.set_pixel(x as i32, y as i32, filter_pixel((px, x as i32, y as i32), img, &filter));
+            let used = acc[channels];
+            let scale = if used != 0.0 && total != 0.0 { total / used } else { 1.0 };
+            for (v, a) in px.iter_mut().zip(acc) {
+                *v = a * scale;
+            }
+            if format == PixelFormat::RGBA {
+                px[3] += img.get_pixel(x as i32, y as i32).a();
+            }
         }
+    });
+
+    out
+}
+
+// Filters with the outer product of a column and a row kernel, i.e. the FilterMatrix m[y][x] = col[y] * row[x],
+// in O(row + col) per pixel instead of O(row * col). Gives the same result as filter_image with that matrix.
+pub fn filter_image_separable<I: GenericFImage + ?Sized>(img: &I, row: &Kernel1D, col: &Kernel1D) -> FImage {
+    separable_filter(img, row, col, row.sum() * col.sum())
+}
+
+// separable matrices are routed through filter_image_separable
+pub fn filter_image<I: GenericFImage + ?Sized>(img: &I, filter: FilterMatrix) -> FImage {
+    if let Some((row, col)) = filter.factors() {
+        return separable_filter(img, row, col, filter.sum());
     }
 
+    let mut out = img.new_like(img.get_pixel_format());
+    let channels = img.get_pixel_format().channel_count();
+
+    for_each_row(&mut out, |y, row| {
+        for (x, px) in row.chunks_exact_mut(channels).enumerate() {
+            let sum = filter_pixel(x as i32, y as i32, img, &filter);
+
+            px.copy_from_slice(&sum[..channels]);
+        }
+    });
+
     out
 }
 
-pub fn fn_filter<FN: FnMut(i32, i32, Pixel) -> Pixel>(img: &FImage, mut func: FN) -> FImage {
-    let mut out = FImage::new(img.width(), img.height(), img.get_pixel_format());
+// Always serial, even with the parallel feature: func is FnMut and gets called one pixel at a time,
+// column by column, on the calling thread. Closures that are Fn + Sync + Send can go through
+// fn_filter_parallel instead, which gives the same output.
+pub fn fn_filter<I: GenericFImage + ?Sized, FN: FnMut(i32, i32, Pixel) -> Pixel>(img: &I, mut func: FN) -> FImage {
+    let mut out = img.new_like(img.get_pixel_format());
     
     for x in 0..img.width() {
         for y in 0..img.height() {
@@ -58,49 +182,68 @@ pub fn fn_filter<FN: FnMut(i32, i32, Pixel) -> Pixel>(img: &FImage, mut func: FN
     out
 }
 
+// fn_filter for closures that can be shared between threads, runs in parallel with the parallel feature
+pub fn fn_filter_parallel<I: GenericFImage + ?Sized, FN: Fn(i32, i32, Pixel) -> Pixel + Sync + Send>(img: &I, func: FN) -> FImage {
+    let mut out = img.new_like(img.get_pixel_format());
+    let channels = img.get_pixel_format().channel_count();
+
+    for_each_row(&mut out, |y, row| {
+        for (x, px) in row.chunks_exact_mut(channels).enumerate() {
+            let (x, y) = (x as i32, y as i32);
+
+            func(x, y, img.get_pixel(x, y)).write_to_slice(px);
+        }
+    });
+
+    out
+}
+
 pub fn combine_images(img1: &FImage, img2: &FImage) -> FImage {
-    let mut out = FImage::new(img1.width(), img1.height(), img1.get_pixel_format());
-    
-    for x in 0..img1.width() {
-        for y in 0..img1.height() {
-            let mut px1 = img1.get_pixel(x as i32, y as i32).slice().to_owned();
+    let mut out = img1.new_like(img1.get_pixel_format());
+    let channels = img1.get_pixel_format().channel_count();
+
+    for_each_row(&mut out, |y, row| {
+        for (x, px) in row.chunks_exact_mut(channels).enumerate() {
             let px2 = img2.get_pixel(x as i32, y as i32);
+            px.copy_from_slice(img1.pixel_slice(x, y));
 
-            for (i, p) in px1.iter_mut().enumerate() {
+            for (i, p) in px.iter_mut().enumerate() {
                 *p += px2.slice()[i];
             }
-
-            out.set_pixel(x as i32, y as i32, Pixel::from_slice(&px1));
         }
-    }
+    });
 
     out
 }
 
 pub fn combine_color_channels(img: &FImage) -> FImage {
-    let mut out = FImage::new(img.width(), img.height(), PixelFormat::Mono);
-    
-    for x in 0..img.width() {
-        for y in 0..img.height() {
-            let px = img.get_pixel(x as i32, y as i32);
+    let mut out = img.new_like(PixelFormat::Mono);
 
-            let sum = px.r() + px.g() + px.b();
+    for_each_row(&mut out, |y, row| {
+        for (x, p) in row.iter_mut().enumerate() {
+            let px = img.get_pixel(x as i32, y as i32);
 
-            out.set_pixel(x as i32, y as i32, Pixel::mono(sum));
+            *p = px.r() + px.g() + px.b();
         }
-    }
+    });
 
     out
 }
 
-#[derive(PartialOrd, PartialEq)]
+#[derive(PartialEq)]
 struct OF32(f32);
 
 impl Eq for OF32 {}
 
+impl PartialOrd for OF32 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for OF32 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-	if let Some(ordering) = self.partial_cmp(other) {
+	if let Some(ordering) = self.0.partial_cmp(&other.0) {
 	    ordering
 	} else {
 	    // Choose what to do with NaNs, for example:
@@ -117,11 +260,15 @@ fn get_label(x: i32, y: i32, map: &HashMap<(i32, i32), i32>) -> i32 {
 }
 
 pub fn watershed(img: &FImage, p1: (i32, i32), p2: (i32, i32)) -> FImage {
+    try_watershed(img, p1, p2).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_watershed(img: &FImage, p1: (i32, i32), p2: (i32, i32)) -> Result<FImage> {
     if !matches!(img.get_pixel_format(), PixelFormat::Mono) {
-        panic!("Image must be mono for watershed!");
+        return Err(Error::UnsupportedPixelFormat { expected: PixelFormat::Mono, found: img.get_pixel_format() });
     }
 
-    let mut out = FImage::new(img.width(), img.height(), PixelFormat::Mono);
+    let mut out = img.new_like(PixelFormat::Mono);
 
     let mut pq = PriorityQueue::new();
     let mut labels = HashMap::new();
@@ -132,15 +279,17 @@ pub fn watershed(img: &FImage, p1: (i32, i32), p2: (i32, i32)) -> FImage {
     labels.insert(p1, 1);
     labels.insert(p2, 2);
 
-    let w = img.width() as i32;
-    let h = img.height() as i32;
-    while pq.len() > 0 {
+    while !pq.is_empty() {
         // The pixel with the highest priority level is extracted from the priority queue. If the neighbors of the extracted pixel that have already been labeled all have the same label, then the pixel is labeled with their label. All non-marked neighbors that are not yet in the priority queue are put into the priority queue.
         let pixel = pq.pop().unwrap();
-        let neighbors = [(pixel.0.0, (pixel.0.1 + 1) % h), (pixel.0.0, (pixel.0.1 + h - 1) % h), ((pixel.0.0 + w - 1) % w, pixel.0.1), ((pixel.0.0 + 1) % w, pixel.0.1)];
+        let (px, py) = pixel.0;
+        // neighbors the border mode has no pixel for are left out
+        let neighbors = [(px, py + 1), (px, py - 1), (px - 1, py), (px + 1, py)].into_iter()
+            .filter_map(|(nx, ny)| img.resolve_coords(nx, ny))
+            .map(|(nx, ny)| (nx as i32, ny as i32));
 
-        let mut labeled = Vec::new(); labeled.reserve(4);
-        let mut unlabeled = Vec::new(); unlabeled.reserve(4);
+        let mut labeled = Vec::with_capacity(4);
+        let mut unlabeled = Vec::with_capacity(4);
 
         for p in neighbors {
             let label = get_label(p.0, p.1, &labels);
@@ -154,7 +303,7 @@ pub fn watershed(img: &FImage, p1: (i32, i32), p2: (i32, i32)) -> FImage {
         // println!("lableled: {:?}", labeled);
         // println!("unlabled: {:?}", unlabeled);
 
-        let mut first_label = labeled.get(0).map(|t| t.1);
+        let mut first_label = labeled.first().map(|t| t.1);
         if labeled.len() > 1 {
             for p in &labeled[1..] {
                 if p.1 != first_label.unwrap() {
@@ -165,11 +314,11 @@ pub fn watershed(img: &FImage, p1: (i32, i32), p2: (i32, i32)) -> FImage {
         }
 
         // first_label will be None if either there are no labeled pixels or the labeled pixels have different labels
-        if first_label.is_some() {
-            labels.insert((pixel.0.0, pixel.0.1), first_label.unwrap());
-            // println!("lableled: {}", first_label.unwrap());
-        } else if !labels.contains_key(&(pixel.0.0, pixel.0.1)){
-            labels.insert((pixel.0.0, pixel.0.1), -1);
+        if let Some(label) = first_label {
+            labels.insert((pixel.0.0, pixel.0.1), label);
+            // println!("lableled: {}", label);
+        } else {
+            labels.entry((pixel.0.0, pixel.0.1)).or_insert(-1);
         }
 
         for p in unlabeled {
@@ -191,5 +340,174 @@ pub fn watershed(img: &FImage, p1: (i32, i32), p2: (i32, i32)) -> FImage {
         }
     }
 
-    out
-}
\ No newline at end of file
+    Ok(out)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float_image::BorderMode;
+    use crate::image_view::Rect;
+
+    fn noise(width: usize, height: usize, format: PixelFormat) -> FImage {
+        let mut img = FImage::new(width, height, format);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = ((i * 7919) % 101) as f32 / 100.0 - 0.25;
+        }
+
+        img
+    }
+
+    fn borders() -> [BorderMode; 5] {
+        [BorderMode::Wrap, BorderMode::Clamp, BorderMode::Mirror, BorderMode::Constant(Pixel::rgba(0.2, 0.4, 0.6, 0.8)), BorderMode::Skip]
+    }
+
+    // the plain single threaded loop the row based filters have to reproduce bit for bit
+    fn serial_filter<I: GenericFImage + ?Sized>(img: &I, filter: &FilterMatrix) -> FImage {
+        let mut out = img.new_like(img.get_pixel_format());
+        let channels = img.get_pixel_format().channel_count();
+
+        for y in 0..img.height() {
+            for x in 0..img.width() {
+                let sum = filter_pixel(x as i32, y as i32, img, filter);
+                out.pixel_slice_mut(x, y).copy_from_slice(&sum[..channels]);
+            }
+        }
+
+        out
+    }
+
+    fn same_bits(a: &FImage, b: &FImage) -> bool {
+        a.data().len() == b.data().len() && a.data().iter().zip(b.data()).all(|(x, y)| x.to_bits() == y.to_bits())
+    }
+
+    #[test]
+    fn row_filters_match_the_serial_loop() {
+        let laplace = [[0.0, 1.0, 0.0], [1.0, -4.0, 1.0], [0.0, 1.0, 0.5]];
+
+        for format in [PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA] {
+            for border in borders() {
+                let mut img = noise(67, 45, format);
+                img.set_border_mode(border);
+
+                assert!(same_bits(&filter_image(&img, FilterMatrix::new(laplace)), &serial_filter(&img, &FilterMatrix::new(laplace))));
+
+                let view = img.view(Rect::new(3, 5, 40, 30));
+                assert!(same_bits(&filter_image(&view, FilterMatrix::new(laplace)), &serial_filter(&view, &FilterMatrix::new(laplace))));
+
+                fn func<'a>(x: i32, y: i32, p: Pixel<'a>) -> Pixel<'a> {
+                    Pixel::rgba(p.r() * x as f32, p.g() - y as f32, p.b().sqrt(), p.a() * 0.5)
+                }
+                assert!(same_bits(&fn_filter_parallel(&img, func), &fn_filter(&img, func)));
+            }
+        }
+    }
+
+    // largest difference relative to b, or absolute for values below 1
+    fn max_difference(a: &FImage, b: &FImage) -> f32 {
+        assert_eq!(a.data().len(), b.data().len());
+        a.data().iter().zip(b.data()).fold(0.0, |m, (x, y)| m.max((x - y).abs() / y.abs().max(1.0)))
+    }
+
+    // col[y] * row[x], optionally rounded to 4 decimals like a hand written table
+    fn outer<const N: usize>(row: &Kernel1D, col: &Kernel1D, rounded: bool) -> [[f32; N]; N] {
+        let mut m = [[0.0; N]; N];
+        for (y, line) in m.iter_mut().enumerate() {
+            for (x, v) in line.iter_mut().enumerate() {
+                let w = col.get(y) * row.get(x);
+                *v = if rounded { (w * 1e4).round() / 1e4 } else { w };
+            }
+        }
+
+        m
+    }
+
+    #[test]
+    fn separable_path_matches_the_2d_filter() {
+        let gaussian = Kernel1D::gaussian(1.04);
+        let slope = Kernel1D::new(&[-1.0, 0.0, 2.0, 1.0, 0.5]);
+        let m: [[f32; 9]; 9] = outer(&gaussian, &gaussian, false);
+        let skewed: [[f32; 5]; 5] = outer(&slope, &Kernel1D::new(&[1.0, 4.0, 6.0, 4.0, 1.0]), false);
+        assert!(FilterMatrix::new(m).is_separable() && FilterMatrix::new(skewed).is_separable());
+
+        for format in [PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA] {
+            for border in borders() {
+                let mut img = noise(31, 23, format);
+                img.set_border_mode(border.clone());
+
+                // the two passes only round in a different order, a couple of ulps apart
+                let reference = serial_filter(&img, &FilterMatrix::new(m));
+                assert!(max_difference(&filter_image(&img, FilterMatrix::new(m)), &reference) <= 3e-7, "{:?} {:?}", format, border);
+                assert!(max_difference(&filter_image_separable(&img, &gaussian, &gaussian), &reference) <= 3e-7, "{:?} {:?}", format, border);
+
+                // unnormalized weights summing to 72 in magnitude scale the rounding with them
+                let reference = serial_filter(&img, &FilterMatrix::new(skewed));
+                assert!(max_difference(&filter_image(&img, FilterMatrix::new(skewed)), &reference) <= 1e-5, "{:?} {:?}", format, border);
+            }
+        }
+    }
+
+    #[test]
+    fn separability_detection() {
+        assert!(FilterMatrix::new([[-1.0, 0.0, 1.0], [-2.0, 0.0, 2.0], [-1.0, 0.0, 1.0]]).is_separable());
+        assert!(!FilterMatrix::new([[0.0, 1.0, 0.0], [1.0, -4.0, 1.0], [0.0, 1.0, 0.0]]).is_separable());
+        assert!(!FilterMatrix::new([[0.0; 3]; 3]).is_separable());
+
+        // rounding the weights to 4 decimals is allowed for by the detection, the factors stay close to the gaussian
+        let gaussian = Kernel1D::gaussian(1.04);
+        let rounded = FilterMatrix::new(outer::<9>(&gaussian, &gaussian, true));
+        assert!(rounded.is_separable());
+        let (row, col) = rounded.to_separable(1e-2).unwrap();
+        assert!(row.weights().iter().zip(gaussian.weights()).all(|(a, b)| (a - b).abs() < 1e-3));
+        assert!(col.weights().iter().zip(gaussian.weights()).all(|(a, b)| (a - b).abs() < 1e-3));
+
+        assert!(Kernel1D::try_new(&[1.0, 1.0]).is_err());
+        assert_eq!(filter_image_separable(&FImage::new(0, 4, PixelFormat::RGB), &gaussian, &gaussian).height(), 4);
+    }
+
+    #[test]
+    fn rounded_gaussian_takes_the_separable_path() {
+        const GAUSSIAN: [[f32; 9]; 9] = [[0.0000, 0.0000, 0.0000, 0.0001, 0.0001, 0.0001, 0.0000, 0.0000, 0.0000],
+                                         [0.0000, 0.0000, 0.0004, 0.0014, 0.0023, 0.0014, 0.0004, 0.0000, 0.0000],
+                                         [0.0000, 0.0004, 0.0037, 0.0146, 0.0232, 0.0146, 0.0037, 0.0004, 0.0000],
+                                         [0.0001, 0.0014, 0.0146, 0.0584, 0.0926, 0.0584, 0.0146, 0.0014, 0.0001],
+                                         [0.0001, 0.0023, 0.0232, 0.0926, 0.1466, 0.0926, 0.0232, 0.0023, 0.0001],
+                                         [0.0001, 0.0014, 0.0146, 0.0584, 0.0926, 0.0584, 0.0146, 0.0014, 0.0001],
+                                         [0.0000, 0.0004, 0.0037, 0.0146, 0.0232, 0.0146, 0.0037, 0.0004, 0.0000],
+                                         [0.0000, 0.0000, 0.0004, 0.0014, 0.0023, 0.0014, 0.0004, 0.0000, 0.0000],
+                                         [0.0000, 0.0000, 0.0000, 0.0001, 0.0001, 0.0001, 0.0000, 0.0000, 0.0000]];
+        assert!(FilterMatrix::new(GAUSSIAN).is_separable());
+        // a step of 1e-4 is too coarse for weights this small
+        assert!(!FilterMatrix::new([[0.0, 0.0001, 0.0], [0.0001, 0.0003, 0.0001], [0.0, 0.0001, 0.0]]).is_separable());
+
+        for format in [PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA] {
+            for border in borders() {
+                let mut img = noise(31, 23, format);
+                img.set_border_mode(border.clone());
+
+                // each of the 81 weights may be off by one step of 1e-4, on inputs below 1
+                let reference = serial_filter(&img, &FilterMatrix::new(GAUSSIAN));
+                let difference = max_difference(&filter_image(&img, FilterMatrix::new(GAUSSIAN)), &reference);
+                assert!(difference <= 81.0 * 1e-4, "{:?} {:?} {}", format, border, difference);
+            }
+        }
+    }
+
+    #[test]
+    fn combining_matches_the_serial_loop() {
+        let a = noise(53, 31, PixelFormat::RGB);
+        let mut b = a.clone();
+        b.data_mut().reverse();
+
+        let sum = combine_images(&a, &b);
+        let mono = combine_color_channels(&a);
+        for y in 0..31 {
+            for x in 0..53 {
+                let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+                for c in 0..3 {
+                    assert_eq!(sum.get_pixel(x, y).slice()[c].to_bits(), (pa.slice()[c] + pb.slice()[c]).to_bits());
+                }
+                assert_eq!(mono.get_pixel(x, y).r().to_bits(), (pa.r() + pa.g() + pa.b()).to_bits());
+            }
+        }
+    }
+}
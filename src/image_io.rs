@@ -0,0 +1,497 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::path::Path;
+
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, ReadChannels, ReadLayers, SmallVec, WritableImage};
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use image::{ImageFormat, ImageOutputFormat, Rgb};
+
+use crate::compositing::CompositeOp;
+use crate::dither::Dither;
+use crate::error::{Error, Result};
+use crate::float_image::{BitDepth, FImage, Pixel, PixelFormat};
+use crate::tone_map::DisplayTransform;
+
+// what happens to the alpha channel of RGBA images on save
+#[derive(Debug, Clone)]
+pub enum AlphaMode {
+    // stored when the format has an alpha channel, dropped otherwise (jpeg, pnm, hdr and pfm)
+    Keep,
+    Discard,
+    // composited over a background color, then dropped
+    Flatten(Pixel<'static>)
+}
+
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    // None picks the format's preferred depth
+    pub bit_depth: Option<BitDepth>,
+    pub alpha: AlphaMode,
+    // None writes the values as they are, integer formats clip them
+    pub display: Option<DisplayTransform>,
+    // only used for integer bit depths
+    pub dither: Dither
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions { bit_depth: None, alpha: AlphaMode::Keep, display: None, dither: Dither::None }
+    }
+}
+
+impl SaveOptions {
+    pub fn new(bit_depth: Option<BitDepth>, alpha: AlphaMode) -> SaveOptions {
+        SaveOptions { bit_depth, alpha, display: None, dither: Dither::None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Image(ImageFormat),
+    // portable float map, which the image crate doesn't handle. Mono or RGB only, alpha is dropped on save
+    Pfm
+}
+
+impl From<ImageFormat> for FileFormat {
+    fn from(format: ImageFormat) -> FileFormat {
+        FileFormat::Image(format)
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileFormat::Image(format) => write!(f, "{:?}", format),
+            FileFormat::Pfm => write!(f, "Pfm")
+        }
+    }
+}
+
+impl FileFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<FileFormat> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("pfm") => Ok(FileFormat::Pfm),
+            _ => Ok(FileFormat::Image(ImageFormat::from_path(path)?))
+        }
+    }
+
+    // guesses the format from the magic bytes at the start of the file
+    pub fn from_bytes(bytes: &[u8]) -> Result<FileFormat> {
+        if bytes.len() > 2 && (bytes.starts_with(b"PF") || bytes.starts_with(b"Pf")) && bytes[2].is_ascii_whitespace() {
+            return Ok(FileFormat::Pfm);
+        }
+
+        Ok(FileFormat::Image(image::guess_format(bytes)?))
+    }
+}
+
+enum Encoder {
+    Image(ImageOutputFormat),
+    Exr,
+    Hdr,
+    Pfm
+}
+
+// what an encoder can store, depths and pixel formats are in order of preference
+struct Target {
+    format: FileFormat,
+    encoder: Encoder,
+    depths: &'static [BitDepth],
+    layouts: &'static [PixelFormat]
+}
+
+const ANY_LAYOUT: &[PixelFormat] = &[PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA];
+const NO_ALPHA: &[PixelFormat] = &[PixelFormat::Mono, PixelFormat::RGB];
+const COLOR_ONLY: &[PixelFormat] = &[PixelFormat::RGB, PixelFormat::RGBA];
+
+impl Target {
+    // the extension picks the flavour of formats that share an ImageFormat, like the pnm family
+    fn new(format: FileFormat, extension: Option<&str>) -> Target {
+        use BitDepth::*;
+
+        let image_format = match format {
+            FileFormat::Image(f) => f,
+            FileFormat::Pfm => return Target { format, encoder: Encoder::Pfm, depths: &[F32], layouts: NO_ALPHA }
+        };
+
+        let (encoder, depths, layouts): (Encoder, &[BitDepth], &[PixelFormat]) = match image_format {
+            ImageFormat::Png | ImageFormat::Tiff => (Encoder::Image(image_format.into()), &[U8, U16], ANY_LAYOUT),
+            ImageFormat::Jpeg => (Encoder::Image(ImageOutputFormat::Jpeg(90)), &[U8], NO_ALPHA),
+            ImageFormat::Pnm => match extension {
+                Some("pbm") => (Encoder::Image(ImageOutputFormat::Pnm(PnmSubtype::Bitmap(SampleEncoding::Binary))), &[U8], &[PixelFormat::Mono]),
+                Some("pgm") => (Encoder::Image(ImageOutputFormat::Pnm(PnmSubtype::Graymap(SampleEncoding::Binary))), &[U8], &[PixelFormat::Mono]),
+                Some("ppm") => (Encoder::Image(ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary))), &[U8], &[PixelFormat::RGB]),
+                // the pnm decoder can't read back alpha, so pam files are stored without it
+                _ => (Encoder::Image(ImageOutputFormat::Pnm(PnmSubtype::ArbitraryMap)), &[U8], NO_ALPHA)
+            },
+            ImageFormat::Farbfeld => (Encoder::Image(image_format.into()), &[U16], &[PixelFormat::RGBA]),
+            // icons are embedded as pngs that the ico decoder only reads back as rgba
+            ImageFormat::Ico => (Encoder::Image(image_format.into()), &[U8], &[PixelFormat::RGBA]),
+            ImageFormat::Qoi => (Encoder::Image(image_format.into()), &[U8], COLOR_ONLY),
+            ImageFormat::OpenExr => (Encoder::Exr, &[F32], ANY_LAYOUT),
+            ImageFormat::Hdr => (Encoder::Hdr, &[F32], &[PixelFormat::RGB]),
+            _ => (Encoder::Image(image_format.into()), &[U8], ANY_LAYOUT)
+        };
+
+        Target { format, encoder, depths, layouts }
+    }
+
+    fn from_path(path: &Path) -> Result<Target> {
+        let format = FileFormat::from_path(path)?;
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+
+        Ok(Target::new(format, extension.as_deref()))
+    }
+
+    fn depth(&self, requested: Option<BitDepth>) -> Result<BitDepth> {
+        match requested {
+            None => Ok(self.depths[0]),
+            Some(depth) if self.depths.contains(&depth) => Ok(depth),
+            Some(depth) => Err(Error::UnsupportedBitDepth { format: self.format, depth })
+        }
+    }
+
+    // closest pixel format the encoder can store
+    fn layout(&self, format: PixelFormat) -> PixelFormat {
+        let preference = match format {
+            PixelFormat::Mono => [PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA],
+            PixelFormat::RGB => [PixelFormat::RGB, PixelFormat::RGBA, PixelFormat::Mono],
+            PixelFormat::RGBA => [PixelFormat::RGBA, PixelFormat::RGB, PixelFormat::Mono]
+        };
+
+        preference.into_iter().find(|f| self.layouts.contains(f)).unwrap_or(format)
+    }
+}
+
+impl FImage {
+    // the format is taken from the file contents, falling back to the extension
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FImage> {
+        let bytes = fs::read(path.as_ref())?;
+        let format = FileFormat::from_bytes(&bytes).or_else(|_| FileFormat::from_path(path))?;
+
+        FImage::decode_with_format(&bytes, format)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<FImage> {
+        FImage::decode_with_format(bytes, FileFormat::from_bytes(bytes)?)
+    }
+
+    pub fn decode_with_format<F: Into<FileFormat>>(bytes: &[u8], format: F) -> Result<FImage> {
+        match format.into() {
+            FileFormat::Pfm => FImage::read_pfm(bytes),
+            FileFormat::Image(ImageFormat::OpenExr) => FImage::read_exr(bytes),
+            FileFormat::Image(ImageFormat::Hdr) => FImage::read_hdr(bytes),
+            FileFormat::Image(format) => Ok(FImage::from_dynamic_image(&image::load_from_memory_with_format(bytes, format)?))
+        }
+    }
+
+    // the format is inferred from the extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.save_with(path, &SaveOptions::default())
+    }
+
+    pub fn save_with<P: AsRef<Path>>(&self, path: P, options: &SaveOptions) -> Result<()> {
+        let target = Target::from_path(path.as_ref())?;
+        let mut file = BufWriter::new(File::create(path)?);
+
+        self.write_target(&mut file, target, options)?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    pub fn encode<F: Into<FileFormat>>(&self, format: F, options: &SaveOptions) -> Result<Vec<u8>> {
+        let mut bytes = Cursor::new(Vec::new());
+        self.write_target(&mut bytes, Target::new(format.into(), None), options)?;
+
+        Ok(bytes.into_inner())
+    }
+
+    fn write_target<W: Write + Seek>(&self, writer: &mut W, target: Target, options: &SaveOptions) -> Result<()> {
+        let depth = target.depth(options.bit_depth)?;
+
+        let source = match &options.display {
+            Some(transform) => self.to_display(transform),
+            None => self.clone()
+        };
+
+        let prepared = match &options.alpha {
+            AlphaMode::Flatten(background) if source.get_pixel_format() == PixelFormat::RGBA => source.flatten(background),
+            AlphaMode::Discard if source.get_pixel_format() == PixelFormat::RGBA => source.to_format(PixelFormat::RGB),
+            _ => source
+        };
+        let mut prepared = prepared.to_format(target.layout(prepared.get_pixel_format()));
+
+        if options.dither != Dither::None {
+            match depth {
+                BitDepth::U8 => prepared.quantize(256, options.dither),
+                BitDepth::U16 => prepared.quantize(65536, options.dither),
+                BitDepth::F32 => {}
+            }
+        }
+
+        match target.encoder {
+            Encoder::Image(output) => prepared.try_to_dynamic_image(depth)?.write_to(writer, output)?,
+            Encoder::Exr => prepared.write_exr(writer)?,
+            Encoder::Hdr => prepared.write_hdr(writer)?,
+            Encoder::Pfm => prepared.write_pfm(writer)?
+        }
+
+        Ok(())
+    }
+
+    // one f32 channel per component, named Y for mono images
+    fn write_exr<W: Write + Seek>(&self, writer: &mut W) -> Result<()> {
+        let names: &[&str] = match self.get_pixel_format() {
+            PixelFormat::Mono => &["Y"],
+            PixelFormat::RGB => &["R", "G", "B"],
+            PixelFormat::RGBA => &["R", "G", "B", "A"]
+        };
+        let channels = names.len();
+
+        let list = names.iter().enumerate().map(|(c, name)| {
+            let samples = self.data().iter().skip(c).step_by(channels).copied().collect();
+            AnyChannel::new(*name, FlatSamples::F32(samples))
+        }).collect();
+
+        let layer = Layer::new((self.width(), self.height()), LayerAttributes::default(), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_vec(list)));
+        Image::from_layer(layer).write().to_buffered(writer)?;
+
+        Ok(())
+    }
+
+    fn read_exr(bytes: &[u8]) -> Result<FImage> {
+        let image = exr::prelude::read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().from_buffered(Cursor::new(bytes))?;
+        let layer = image.layer_data;
+
+        let channel = |name: &str| layer.channel_data.list.iter().find(|c| c.name.eq(name)).map(|c| &c.sample_data);
+        let alpha = channel("A");
+
+        // color channels in output order, gray images repeat their single channel
+        let sources = match (channel("R"), channel("G"), channel("B"), channel("Y")) {
+            (Some(r), Some(g), Some(b), _) => vec![r, g, b],
+            (_, _, _, Some(y)) => vec![y],
+            _ if layer.channel_data.list.len() == 1 => vec![&layer.channel_data.list[0].sample_data],
+            _ => return Err(Error::Codec("exr image has no RGB or Y channels".to_owned()))
+        };
+
+        let format = match (sources.len(), alpha) {
+            (_, Some(_)) => PixelFormat::RGBA,
+            (1, None) => PixelFormat::Mono,
+            _ => PixelFormat::RGB
+        };
+
+        let mut out = FImage::new(layer.size.width(), layer.size.height(), format);
+        for (i, px) in out.data_mut().chunks_exact_mut(format.channel_count()).enumerate() {
+            for (c, v) in px.iter_mut().enumerate() {
+                let samples = match (c, alpha) {
+                    (3, Some(a)) => a,
+                    _ => sources[c.min(sources.len() - 1)]
+                };
+                *v = samples.value_by_flat_index(i).to_f32();
+            }
+        }
+
+        Ok(out)
+    }
+
+    // rgbe stores non-negative colors with a shared 8 bit exponent, so it's close but not exact
+    fn write_hdr<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let pixels: Vec<Rgb<f32>> = self.pixels().map(|p| Rgb([p.r().max(0.0), p.g().max(0.0), p.b().max(0.0)])).collect();
+        HdrEncoder::new(writer).encode(&pixels, self.width(), self.height())?;
+
+        Ok(())
+    }
+
+    // the image crate's generic hdr path tone maps down to 8 bits, so this reads the floats directly
+    fn read_hdr(bytes: &[u8]) -> Result<FImage> {
+        let decoder = HdrDecoder::new(Cursor::new(bytes))?;
+        let meta = decoder.metadata();
+
+        let mut out = FImage::new(meta.width as usize, meta.height as usize, PixelFormat::RGB);
+        for (px, src) in out.data_mut().chunks_exact_mut(3).zip(decoder.read_image_hdr()?) {
+            px.copy_from_slice(&src.0);
+        }
+
+        Ok(out)
+    }
+
+    // little endian, rows stored bottom to top
+    fn write_pfm<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let magic = if self.get_pixel_format() == PixelFormat::Mono { "Pf" } else { "PF" };
+        write!(writer, "{}\n{} {}\n-1.0\n", magic, self.width(), self.height())?;
+
+        for row in self.rows().collect::<Vec<_>>().into_iter().rev() {
+            for v in row {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_pfm(bytes: &[u8]) -> Result<FImage> {
+        let invalid = |msg: &str| Error::Codec(format!("invalid pfm file: {}", msg));
+
+        // magic, width, height and scale separated by whitespace, then a single whitespace byte before the data
+        let mut fields = Vec::with_capacity(4);
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated header"));
+            }
+            fields.push(std::str::from_utf8(&bytes[start..pos]).map_err(|_| invalid("header is not ascii"))?);
+        }
+        pos += 1;
+
+        let format = match fields[0] {
+            "Pf" => PixelFormat::Mono,
+            "PF" => PixelFormat::RGB,
+            _ => return Err(invalid("unknown magic"))
+        };
+        let width: usize = fields[1].parse().map_err(|_| invalid("bad width"))?;
+        let height: usize = fields[2].parse().map_err(|_| invalid("bad height"))?;
+        let scale: f32 = fields[3].parse().map_err(|_| invalid("bad scale"))?;
+
+        // the header is untrusted, check it against the file size before allocating
+        let len = width.checked_mul(height).and_then(|n| n.checked_mul(format.channel_count())).and_then(|n| n.checked_mul(4));
+        let data = len.and_then(|len| bytes.get(pos..pos.checked_add(len)?)).ok_or_else(|| invalid("truncated pixel data"))?;
+
+        let mut out = FImage::new(width, height, format);
+        let stride = (width * format.channel_count()).max(1);
+
+        // a negative scale means little endian data
+        for (row, src) in out.rows_mut().zip(data.chunks_exact(stride * 4).rev()) {
+            for (v, b) in row.iter_mut().zip(src.chunks_exact(4)) {
+                let b = [b[0], b[1], b[2], b[3]];
+                *v = if scale < 0.0 { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) };
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn flatten(&self, background: &Pixel) -> FImage {
+        let background = Pixel::rgb(background.r(), background.g(), background.b());
+        let mut out = self.new_like(PixelFormat::RGB);
+
+        for (dst, src) in out.data_mut().chunks_exact_mut(3).zip(self.pixels()) {
+            src.composite(&background, CompositeOp::Over).write_to_slice(dst);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::ImageFormat;
+
+    use super::{AlphaMode, FileFormat, SaveOptions};
+    use crate::error::Error;
+    use crate::float_image::{BitDepth, FImage, PixelFormat};
+
+    fn gradient(format: PixelFormat) -> FImage {
+        let mut img = FImage::new(5, 3, format);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = (i % 256) as f32 / 255.0;
+        }
+
+        img
+    }
+
+    #[test]
+    fn formats_are_inferred() {
+        assert_eq!(FileFormat::from_path("a/b.PFM").unwrap(), FileFormat::Pfm);
+        assert_eq!(FileFormat::from_path("b.png").unwrap(), FileFormat::Image(ImageFormat::Png));
+        assert!(FileFormat::from_path("b.unknown").is_err());
+
+        let png = gradient(PixelFormat::RGB).encode(ImageFormat::Png, &SaveOptions::default()).unwrap();
+        assert_eq!(FileFormat::from_bytes(&png).unwrap(), FileFormat::Image(ImageFormat::Png));
+        assert_eq!(FileFormat::from_bytes(b"Pf\n1 1\n-1.0\n").unwrap(), FileFormat::Pfm);
+    }
+
+    #[test]
+    fn png_round_trips() {
+        for format in [PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA] {
+            let img = gradient(format);
+
+            for depth in [BitDepth::U8, BitDepth::U16] {
+                let bytes = img.encode(ImageFormat::Png, &SaveOptions::new(Some(depth), AlphaMode::Keep)).unwrap();
+                let back = FImage::decode(&bytes).unwrap();
+
+                assert_eq!(back.get_pixel_format(), format);
+                assert_eq!(back.data(), img.data(), "{:?} {:?}", format, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_modes_and_depths() {
+        let img = gradient(PixelFormat::RGBA);
+
+        let discarded = FImage::decode(&img.encode(ImageFormat::Png, &SaveOptions::new(None, AlphaMode::Discard)).unwrap()).unwrap();
+        assert_eq!(discarded.get_pixel_format(), PixelFormat::RGB);
+        // jpeg can't store alpha, Keep drops it
+        let jpeg = FImage::decode(&img.encode(ImageFormat::Jpeg, &SaveOptions::default()).unwrap()).unwrap();
+        assert_eq!(jpeg.get_pixel_format(), PixelFormat::RGB);
+
+        let err = img.encode(ImageFormat::Jpeg, &SaveOptions::new(Some(BitDepth::U16), AlphaMode::Keep));
+        assert!(matches!(err, Err(Error::UnsupportedBitDepth { depth: BitDepth::U16, .. })));
+    }
+
+    #[test]
+    fn float_formats_round_trip() {
+        let mut img = gradient(PixelFormat::RGBA);
+        img.data_mut().iter_mut().enumerate().for_each(|(i, v)| *v = *v * 40.0 - 3.0 + i as f32 * 1e-3);
+
+        for format in [PixelFormat::Mono, PixelFormat::RGB, PixelFormat::RGBA] {
+            let img = img.to_format(format);
+
+            let exr = FImage::decode(&img.encode(ImageFormat::OpenExr, &SaveOptions::default()).unwrap()).unwrap();
+            assert_eq!(exr.get_pixel_format(), format);
+            assert_eq!(exr.data(), img.data());
+
+            // pfm has no alpha channel
+            let pfm = FImage::decode(&img.encode(FileFormat::Pfm, &SaveOptions::default()).unwrap()).unwrap();
+            let expected = if format == PixelFormat::RGBA { img.to_format(PixelFormat::RGB) } else { img.clone() };
+            assert_eq!(pfm.get_pixel_format(), expected.get_pixel_format());
+            assert_eq!(pfm.data(), expected.data());
+        }
+
+        // rgbe clips negatives and keeps about 8 bits of mantissa
+        let rgb = img.to_format(PixelFormat::RGB);
+        let hdr = FImage::decode_with_format(&rgb.encode(ImageFormat::Hdr, &SaveOptions::default()).unwrap(), ImageFormat::Hdr).unwrap();
+        for (a, b) in hdr.data().iter().zip(rgb.data()) {
+            assert!((a - b.max(0.0)).abs() <= b.abs() / 64.0 + 1e-3, "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn bad_pfm_headers_are_rejected() {
+        let pfm = gradient(PixelFormat::RGB).encode(FileFormat::Pfm, &SaveOptions::default()).unwrap();
+        assert!(FImage::decode(&pfm[..pfm.len() - 1]).is_err());
+
+        // sizes that would overflow or need far more data than the file has fail before allocating
+        for header in ["PF\n18446744073709551615 3\n-1.0\n", "Pf\n4611686018427387904 1\n-1.0\n", "PF\n100000 100000\n-1.0\n"] {
+            let mut bytes = header.as_bytes().to_vec();
+            bytes.extend_from_slice(&[0; 64]);
+            assert!(matches!(FImage::decode(&bytes), Err(Error::Codec(_))), "{}", header);
+        }
+
+        assert!(FImage::decode(b"PF\n2 x\n-1.0\n").is_err());
+        assert!(FImage::decode(b"PF\n2").is_err());
+    }
+}
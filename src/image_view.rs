@@ -0,0 +1,393 @@
+use crate::blend_mode::BlendMode;
+use crate::compositing::CompositeOp;
+use crate::error::{Error, Result};
+use crate::float_image::{FImage, Pixel, PixelFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    pub fn fits_in(&self, width: usize, height: usize) -> bool {
+        self.x.checked_add(self.width).is_some_and(|right| right <= width)
+            && self.y.checked_add(self.height).is_some_and(|bottom| bottom <= height)
+    }
+}
+
+// read access shared by FImage and its views, so filters can run on either (and across threads)
+pub trait GenericFImage: Sync {
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    fn get_pixel_format(&self) -> PixelFormat;
+
+    fn get_pixel_checked(&self, x: i32, y: i32) -> Option<Pixel<'_>>;
+
+    // direct access to an in-bounds pixel
+    fn pixel_slice(&self, x: usize, y: usize) -> &[f32];
+
+    // blank image the size of this one, carrying over border mode and color space
+    fn new_like(&self, format: PixelFormat) -> FImage;
+
+    fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height()
+    }
+
+    fn get_pixel(&self, x: i32, y: i32) -> Pixel<'_> {
+        self.get_pixel_checked(x, y).unwrap_or(Pixel::from_array([0.0; 4], self.get_pixel_format()))
+    }
+}
+
+// write access, writes that land outside the image are dropped unless it wraps (FImage with BorderMode::Wrap)
+pub trait GenericFImageMut: GenericFImage {
+    fn set_pixel(&mut self, x: i32, y: i32, pixel: Pixel);
+
+    fn pixel_slice_mut(&mut self, x: usize, y: usize) -> &mut [f32];
+
+    fn set_pixel_composite(&mut self, x: i32, y: i32, pixel: Pixel, op: CompositeOp) {
+        if self.in_bounds(x, y) {
+            let new_pixel = pixel.composite(&self.get_pixel(x, y), op);
+            self.set_pixel(x, y, new_pixel);
+        }
+    }
+
+    fn set_pixel_blended(&mut self, x: i32, y: i32, pixel: Pixel) {
+        self.set_pixel_composite(x, y, pixel, CompositeOp::Over);
+    }
+
+    fn set_pixel_blend_mode(&mut self, x: i32, y: i32, pixel: Pixel, mode: BlendMode, opacity: f32) {
+        if self.in_bounds(x, y) {
+            let new_pixel = pixel.blend(&self.get_pixel(x, y), mode, opacity);
+            self.set_pixel(x, y, new_pixel);
+        }
+    }
+}
+
+impl GenericFImage for FImage {
+    fn width(&self) -> usize {
+        FImage::width(self)
+    }
+
+    fn height(&self) -> usize {
+        FImage::height(self)
+    }
+
+    fn get_pixel_format(&self) -> PixelFormat {
+        FImage::get_pixel_format(self)
+    }
+
+    fn get_pixel_checked(&self, x: i32, y: i32) -> Option<Pixel<'_>> {
+        FImage::get_pixel_checked(self, x, y)
+    }
+
+    fn pixel_slice(&self, x: usize, y: usize) -> &[f32] {
+        FImage::pixel_slice(self, x, y)
+    }
+
+    fn new_like(&self, format: PixelFormat) -> FImage {
+        FImage::new_like(self, format)
+    }
+
+    fn in_bounds(&self, x: i32, y: i32) -> bool {
+        FImage::in_bounds(self, x, y)
+    }
+
+    fn get_pixel(&self, x: i32, y: i32) -> Pixel<'_> {
+        FImage::get_pixel(self, x, y)
+    }
+}
+
+impl GenericFImageMut for FImage {
+    fn set_pixel(&mut self, x: i32, y: i32, pixel: Pixel) {
+        FImage::set_pixel(self, x, y, pixel)
+    }
+
+    fn pixel_slice_mut(&mut self, x: usize, y: usize) -> &mut [f32] {
+        FImage::pixel_slice_mut(self, x, y)
+    }
+
+    fn set_pixel_composite(&mut self, x: i32, y: i32, pixel: Pixel, op: CompositeOp) {
+        FImage::set_pixel_composite(self, x, y, pixel, op)
+    }
+
+    fn set_pixel_blended(&mut self, x: i32, y: i32, pixel: Pixel) {
+        FImage::set_pixel_blended(self, x, y, pixel)
+    }
+
+    fn set_pixel_blend_mode(&mut self, x: i32, y: i32, pixel: Pixel, mode: BlendMode, opacity: f32) {
+        FImage::set_pixel_blend_mode(self, x, y, pixel, mode, opacity)
+    }
+}
+
+// Borrowed rectangular region of an FImage. Reads past the edge of the view see the
+// surrounding image (and its border mode), so filters behave as they would on the whole image.
+pub struct FImageView<'a> {
+    image: &'a FImage,
+    rect: Rect
+}
+
+pub struct FImageViewMut<'a> {
+    image: &'a mut FImage,
+    rect: Rect
+}
+
+impl<'a> FImageView<'a> {
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [f32]> + 'a {
+        let rect = self.rect;
+        let channels = self.image.get_pixel_format().channel_count();
+        let stride = (self.image.width() * channels).max(1);
+
+        self.image.data().chunks_exact(stride).skip(rect.y).take(rect.height)
+            .map(move |row| &row[rect.x * channels..(rect.x + rect.width) * channels])
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel<'a>> + 'a {
+        let channels = self.image.get_pixel_format().channel_count();
+
+        self.rows().flat_map(move |row| row.chunks_exact(channels).map(Pixel::from_slice))
+    }
+
+    // copies the region out into a new image
+    pub fn to_image(&self) -> FImage {
+        let mut out = self.new_like(self.image.get_pixel_format());
+
+        for (dst, src) in out.rows_mut().zip(self.rows()) {
+            dst.copy_from_slice(src);
+        }
+
+        out
+    }
+}
+
+impl FImageViewMut<'_> {
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[f32]> {
+        FImageView { image: self.image, rect: self.rect }.rows()
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [f32]> {
+        let rect = self.rect;
+        let channels = self.image.get_pixel_format().channel_count();
+        let stride = (self.image.width() * channels).max(1);
+
+        self.image.data_mut().chunks_exact_mut(stride).skip(rect.y).take(rect.height)
+            .map(move |row| &mut row[rect.x * channels..(rect.x + rect.width) * channels])
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel<'_>> {
+        FImageView { image: self.image, rect: self.rect }.pixels()
+    }
+
+    // (x, y, channels) with coordinates relative to the view
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut [f32])> {
+        let channels = self.image.get_pixel_format().channel_count();
+
+        self.rows_mut().enumerate().flat_map(move |(y, row)| {
+            row.chunks_exact_mut(channels).enumerate().map(move |(x, px)| (x, y, px))
+        })
+    }
+
+    pub fn to_image(&self) -> FImage {
+        FImageView { image: self.image, rect: self.rect }.to_image()
+    }
+}
+
+impl GenericFImage for FImageView<'_> {
+    fn width(&self) -> usize {
+        self.rect.width
+    }
+
+    fn height(&self) -> usize {
+        self.rect.height
+    }
+
+    fn get_pixel_format(&self) -> PixelFormat {
+        self.image.get_pixel_format()
+    }
+
+    fn get_pixel_checked(&self, x: i32, y: i32) -> Option<Pixel<'_>> {
+        self.image.get_pixel_checked(x + self.rect.x as i32, y + self.rect.y as i32)
+    }
+
+    fn pixel_slice(&self, x: usize, y: usize) -> &[f32] {
+        assert!(x < self.rect.width && y < self.rect.height, "pixel is outside the view");
+        self.image.pixel_slice(x + self.rect.x, y + self.rect.y)
+    }
+
+    fn new_like(&self, format: PixelFormat) -> FImage {
+        let mut out = FImage::new(self.rect.width, self.rect.height, format);
+        out.set_border_mode(self.image.border_mode().clone());
+        out.set_color_space(self.image.color_space());
+
+        out
+    }
+}
+
+impl GenericFImage for FImageViewMut<'_> {
+    fn width(&self) -> usize {
+        self.rect.width
+    }
+
+    fn height(&self) -> usize {
+        self.rect.height
+    }
+
+    fn get_pixel_format(&self) -> PixelFormat {
+        self.image.get_pixel_format()
+    }
+
+    fn get_pixel_checked(&self, x: i32, y: i32) -> Option<Pixel<'_>> {
+        self.image.get_pixel_checked(x + self.rect.x as i32, y + self.rect.y as i32)
+    }
+
+    fn pixel_slice(&self, x: usize, y: usize) -> &[f32] {
+        assert!(x < self.rect.width && y < self.rect.height, "pixel is outside the view");
+        self.image.pixel_slice(x + self.rect.x, y + self.rect.y)
+    }
+
+    fn new_like(&self, format: PixelFormat) -> FImage {
+        FImageView { image: self.image, rect: self.rect }.new_like(format)
+    }
+}
+
+impl GenericFImageMut for FImageViewMut<'_> {
+    // writes outside the view are dropped so they can't touch the rest of the image
+    fn set_pixel(&mut self, x: i32, y: i32, pixel: Pixel) {
+        if self.in_bounds(x, y) {
+            self.image.set_pixel(x + self.rect.x as i32, y + self.rect.y as i32, pixel);
+        }
+    }
+
+    fn pixel_slice_mut(&mut self, x: usize, y: usize) -> &mut [f32] {
+        assert!(x < self.rect.width && y < self.rect.height, "pixel is outside the view");
+        self.image.pixel_slice_mut(x + self.rect.x, y + self.rect.y)
+    }
+}
+
+impl FImage {
+    pub fn view(&self, rect: Rect) -> FImageView<'_> {
+        self.try_view(rect).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_view(&self, rect: Rect) -> Result<FImageView<'_>> {
+        if !rect.fits_in(self.width(), self.height()) {
+            return Err(Error::InvalidRect { rect, width: self.width(), height: self.height() });
+        }
+
+        Ok(FImageView { image: self, rect })
+    }
+
+    pub fn view_mut(&mut self, rect: Rect) -> FImageViewMut<'_> {
+        self.try_view_mut(rect).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_view_mut(&mut self, rect: Rect) -> Result<FImageViewMut<'_>> {
+        if !rect.fits_in(self.width(), self.height()) {
+            return Err(Error::InvalidRect { rect, width: self.width(), height: self.height() });
+        }
+
+        Ok(FImageViewMut { image: self, rect })
+    }
+
+    fn full_rect(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[f32]> {
+        FImageView { image: self, rect: self.full_rect() }.rows()
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [f32]> {
+        let stride = (self.width() * self.get_pixel_format().channel_count()).max(1);
+
+        self.data_mut().chunks_exact_mut(stride)
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel<'_>> {
+        let channels = self.get_pixel_format().channel_count();
+
+        self.data().chunks_exact(channels).map(Pixel::from_slice)
+    }
+
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut [f32])> {
+        let width = self.width().max(1);
+        let channels = self.get_pixel_format().channel_count();
+
+        self.data_mut().chunks_exact_mut(channels).enumerate().map(move |(i, px)| (i % width, i / width, px))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GenericFImage, GenericFImageMut, Rect};
+    use crate::error::Error;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+    use crate::image_filter::fn_filter;
+
+    fn numbered(width: usize, height: usize) -> FImage {
+        let mut img = FImage::new(width, height, PixelFormat::Mono);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = i as f32;
+        }
+
+        img
+    }
+
+    #[test]
+    fn rects_that_dont_fit_are_rejected() {
+        let mut img = numbered(4, 3);
+
+        assert!(img.try_view(Rect::new(1, 1, 3, 2)).is_ok());
+        assert!(img.try_view(Rect::new(0, 0, 0, 0)).is_ok());
+        assert!(matches!(img.try_view(Rect::new(2, 0, 3, 1)), Err(Error::InvalidRect { .. })));
+        assert!(matches!(img.try_view(Rect::new(1, usize::MAX, 1, 2)), Err(Error::InvalidRect { .. })));
+        assert!(matches!(img.try_view_mut(Rect::new(usize::MAX, 0, usize::MAX, 1)), Err(Error::InvalidRect { .. })));
+        assert!(!Rect::new(1, 0, usize::MAX, 1).fits_in(usize::MAX, 1));
+    }
+
+    #[test]
+    fn views_read_their_region() {
+        let img = numbered(4, 3);
+        let view = img.view(Rect::new(1, 1, 2, 2));
+
+        assert_eq!(view.rows().collect::<Vec<_>>(), [&[5.0, 6.0][..], &[9.0, 10.0][..]]);
+        assert_eq!(view.pixels().map(|p| p.r()).collect::<Vec<_>>(), [5.0, 6.0, 9.0, 10.0]);
+        assert_eq!(view.to_image().data(), &[5.0, 6.0, 9.0, 10.0]);
+        // reads past the edge of the view see the rest of the image
+        assert_eq!(view.get_pixel(-1, 0).r(), 4.0);
+        assert_eq!(fn_filter(&view, |_, _, p| Pixel::mono(p.r() * 2.0)).data(), &[10.0, 12.0, 18.0, 20.0]);
+    }
+
+    #[test]
+    fn mutable_views_only_write_inside() {
+        let mut img = numbered(4, 3);
+        {
+            let mut view = img.view_mut(Rect::new(2, 0, 2, 2));
+            for (x, y, px) in view.enumerate_pixels_mut() {
+                px[0] = -((x + 10 * y) as f32);
+            }
+            view.set_pixel(-1, 0, Pixel::mono(100.0));
+            view.set_pixel(0, 2, Pixel::mono(100.0));
+        }
+
+        assert_eq!(img.data(), &[0.0, 1.0, 0.0, -1.0, 4.0, 5.0, -10.0, -11.0, 8.0, 9.0, 10.0, 11.0]);
+        assert_eq!(img.rows().count(), 3);
+        assert_eq!(img.pixels().count(), 12);
+    }
+}
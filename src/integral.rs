@@ -0,0 +1,384 @@
+use crate::error::{Error, Result};
+use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+use crate::image_view::Rect;
+
+// (start, end, multiplicity) of a span of the table along one axis
+type Piece = (i64, i64, f64);
+
+// Splits the window [a, b) along an axis of length n into spans of [0, n) that the border mode reads,
+// each with the number of times it is read. Constant and Skip borders only keep the part inside.
+fn axis_pieces(a: i64, b: i64, n: i64, border: &BorderMode) -> Vec<Piece> {
+    let len = b - a;
+    let mut pieces = Vec::new();
+
+    match border {
+        BorderMode::Wrap => {
+            let (full, rest, start) = (len / n, len % n, a.rem_euclid(n));
+            pieces.push((0, n, full as f64));
+            pieces.push((start, (start + rest).min(n), 1.0));
+            pieces.push((0, start + rest - n, 1.0));
+        },
+        BorderMode::Clamp => {
+            pieces.push((0, 1, (b.min(0) - a).max(0) as f64));
+            pieces.push((a.max(0), b.min(n), 1.0));
+            pieces.push((n - 1, n, (b - a.max(n)).max(0) as f64));
+        },
+        BorderMode::Mirror if n > 1 => {
+            // one period reads 0..n and then n-2 down to 1 (dcb|abcd|cba)
+            let period = 2 * (n - 1);
+            let (full, rest, start) = (len / period, len % period, a.rem_euclid(period));
+            pieces.push((0, n, full as f64));
+            pieces.push((1, n - 1, full as f64));
+
+            // the rest of the window as spans of the unfolded period, ascending below n and descending above
+            for (u0, u1) in [(start, (start + rest).min(period)), (0, start + rest - period)] {
+                pieces.push((u0, u1.min(n), 1.0));
+                pieces.push((period - u1 + 1, period - u0.max(n) + 1, 1.0));
+            }
+        },
+        BorderMode::Mirror => pieces.push((0, 1, len as f64)),
+        BorderMode::Constant(_) | BorderMode::Skip => pieces.push((a.max(0), b.min(n), 1.0))
+    }
+
+    pieces.retain(|&(start, end, times)| start < end && times > 0.0);
+    pieces
+}
+
+// Summed-area table, any rectangle sum is four lookups whatever its size. Sums are kept in f64 so
+// large areas don't lose the small values, and every cell also counts the pixels it covers.
+pub struct IntegralImage {
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    // windows that reach past the edges are summed from the pixels the border mode maps them onto
+    border: BorderMode,
+    // the border constant with the table's value map applied
+    constant: [f64; 4],
+    // (width + 1) x (height + 1) cells of channels + 1 entries, the count last
+    sums: Vec<f64>
+}
+
+impl IntegralImage {
+    pub fn new(img: &FImage) -> IntegralImage {
+        IntegralImage::build(img, |_, v| v)
+    }
+
+    // table of value(c, v) for every value v of channel c in the image
+    fn build<F: Fn(usize, f64) -> f64>(img: &FImage, value: F) -> IntegralImage {
+        let channels = img.get_pixel_format().channel_count();
+        let stride = channels + 1;
+        let (w, h) = (img.width(), img.height());
+
+        let mut sums = vec![0.0f64; (w + 1) * (h + 1) * stride];
+        let mut row = vec![0.0f64; stride];
+        for y in 0..h {
+            row.fill(0.0);
+            for x in 0..w {
+                for (c, (acc, &v)) in row.iter_mut().zip(img.pixel_slice(x, y)).enumerate() {
+                    *acc += value(c, v as f64);
+                }
+                row[channels] += 1.0;
+
+                let above = (x + 1 + y * (w + 1)) * stride;
+                let here = above + (w + 1) * stride;
+                for c in 0..stride {
+                    sums[here + c] = sums[above + c] + row[c];
+                }
+            }
+        }
+
+        let mut constant = [0.0; 4];
+        if let BorderMode::Constant(p) = img.border_mode() {
+            let p = Pixel::from_array([p.r(), p.g(), p.b(), p.a()], img.get_pixel_format());
+            for (c, (k, &v)) in constant.iter_mut().zip(p.slice()).enumerate() {
+                *k = value(c, v as f64);
+            }
+        }
+
+        IntegralImage { width: w, height: h, format: img.get_pixel_format(), border: img.border_mode().clone(), constant, sums }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    // sums over [x0, x1) x [y0, y1) clipped to the image, the count last
+    fn window(&self, x0: i64, y0: i64, x1: i64, y1: i64) -> [f64; 5] {
+        let stride = self.format.channel_count() + 1;
+        let (w, h) = (self.width as i64, self.height as i64);
+        let cx = |x: i64| x.clamp(0, w) as usize;
+        let cy = |y: i64| y.clamp(0, h) as usize;
+        let cell = |x: usize, y: usize| (x + y * (self.width + 1)) * stride;
+
+        let (x0, x1, y0, y1) = (cx(x0), cx(x1), cy(y0), cy(y1));
+        let mut out = [0.0; 5];
+        if x1 <= x0 || y1 <= y0 {
+            return out;
+        }
+
+        let (a, b, c, d) = (cell(x0, y0), cell(x1, y0), cell(x0, y1), cell(x1, y1));
+        for (i, v) in out[..stride].iter_mut().enumerate() {
+            *v = self.sums[d + i] - self.sums[b + i] - self.sums[c + i] + self.sums[a + i];
+        }
+
+        out
+    }
+
+    // the pieces of the (2 radius + 1) windows around every column and every row
+    fn axis_windows(&self, radius: usize) -> (Vec<Vec<Piece>>, Vec<Vec<Piece>>) {
+        let r = radius.min(i32::MAX as usize) as i64;
+        let along = |n: usize| (0..n as i64).map(|i| axis_pieces(i - r, i + r + 1, n as i64, &self.border)).collect();
+
+        (along(self.width), along(self.height))
+    }
+
+    // Sums over the window made of the given column and row pieces, counting pixels once per read and
+    // constant border pixels for whatever the window has outside the image. Costs the same for any radius.
+    fn window_around(&self, xs: &[Piece], ys: &[Piece], radius: usize) -> [f64; 5] {
+        let channels = self.format.channel_count();
+        let mut out = [0.0; 5];
+
+        for &(x0, x1, times_x) in xs {
+            for &(y0, y1, times_y) in ys {
+                let s = self.window(x0, y0, x1, y1);
+                for (v, part) in out[..=channels].iter_mut().zip(s) {
+                    *v += times_x * times_y * part;
+                }
+            }
+        }
+
+        if let BorderMode::Constant(_) = self.border {
+            let side = 2.0 * radius.min(i32::MAX as usize) as f64 + 1.0;
+            let outside = side * side - out[channels];
+            for (v, c) in out[..channels].iter_mut().zip(self.constant) {
+                *v += outside * c;
+            }
+            out[channels] += outside;
+        }
+
+        out
+    }
+
+    fn check_rect(&self, rect: &Rect) -> Result<()> {
+        if rect.fits_in(self.width, self.height) {
+            Ok(())
+        } else {
+            Err(Error::InvalidRect { rect: *rect, width: self.width, height: self.height })
+        }
+    }
+
+    pub fn sum(&self, rect: Rect) -> Pixel<'static> {
+        self.try_sum(rect).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_sum(&self, rect: Rect) -> Result<Pixel<'static>> {
+        self.check_rect(&rect)?;
+
+        let s = self.window(rect.x as i64, rect.y as i64, (rect.x + rect.width) as i64, (rect.y + rect.height) as i64);
+
+        Ok(Pixel::from_array([s[0] as f32, s[1] as f32, s[2] as f32, s[3] as f32], self.format))
+    }
+
+    // NaN for an empty rect
+    pub fn mean(&self, rect: Rect) -> Pixel<'static> {
+        self.try_mean(rect).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_mean(&self, rect: Rect) -> Result<Pixel<'static>> {
+        let sum = self.try_sum(rect)?;
+        let n = (rect.width * rect.height) as f32;
+
+        Ok(Pixel::from_array([sum.r() / n, sum.g() / n, sum.b() / n, sum.a() / n], self.format))
+    }
+}
+
+impl FImage {
+    pub fn integral_image(&self) -> IntegralImage {
+        IntegralImage::new(self)
+    }
+
+    // (2 radius + 1)^2 box mean of every channel, alpha included, at the same cost for any radius.
+    // The border mode decides what lies outside the image, skipped pixels are left out of the mean.
+    pub fn integral_box_blur(&self, radius: usize) -> FImage {
+        let table = IntegralImage::build(self, |_, v| v);
+        let (xs, ys) = table.axis_windows(radius);
+        let mut out = self.new_like(self.get_pixel_format());
+
+        for (x, y, px) in out.enumerate_pixels_mut() {
+            let s = table.window_around(&xs[x], &ys[y], radius);
+            let n = s[px.len()];
+            for (v, sum) in px.iter_mut().zip(s) {
+                *v = (sum / n) as f32;
+            }
+        }
+
+        out
+    }
+
+    // Mean and population variance of the (2 radius + 1)^2 window around every pixel, per channel,
+    // with the border handled like integral_box_blur. The tables hold values shifted by the image mean,
+    // so E[x^2] - E[x]^2 doesn't cancel out for images that sit far from zero. Windows whose own mean is
+    // far from the image mean still lose precision that way.
+    pub fn local_mean_variance(&self, radius: usize) -> (FImage, FImage) {
+        let channels = self.get_pixel_format().channel_count();
+        let mut shift = [0.0f64; 4];
+        for px in self.data().chunks_exact(channels) {
+            for (s, &v) in shift.iter_mut().zip(px) {
+                *s += v as f64;
+            }
+        }
+        let count = (self.width() * self.height()).max(1) as f64;
+        shift.iter_mut().for_each(|s| *s /= count);
+
+        let sums = IntegralImage::build(self, |c, v| v - shift[c]);
+        let squares = IntegralImage::build(self, |c, v| (v - shift[c]) * (v - shift[c]));
+        let (xs, ys) = sums.axis_windows(radius);
+        let mut mean = self.new_like(self.get_pixel_format());
+        let mut variance = self.new_like(self.get_pixel_format());
+
+        for ((x, y, m), v) in mean.enumerate_pixels_mut().zip(variance.data_mut().chunks_exact_mut(channels)) {
+            let s = sums.window_around(&xs[x], &ys[y], radius);
+            let sq = squares.window_around(&xs[x], &ys[y], radius);
+            let n = s[m.len()];
+
+            for c in 0..m.len() {
+                let mu = s[c] / n;
+                m[c] = (mu + shift[c]) as f32;
+                v[c] = (sq[c] / n - mu * mu).max(0.0) as f32;
+            }
+        }
+
+        (mean, variance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+    use crate::image_view::Rect;
+
+    fn noise(w: usize, h: usize, format: PixelFormat) -> FImage {
+        let mut img = FImage::new(w, h, format);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = ((i * 7919) % 101) as f32 / 100.0;
+        }
+
+        img
+    }
+
+    // window means read pixel by pixel through the border mode
+    fn brute_force(img: &FImage, radius: usize) -> (FImage, FImage) {
+        let channels = img.get_pixel_format().channel_count();
+        let (mut mean, mut variance) = (img.clone(), img.clone());
+        let r = radius as i32;
+
+        for y in 0..img.height() as i32 {
+            for x in 0..img.width() as i32 {
+                let (mut sum, mut sq, mut n) = ([0.0f64; 4], [0.0f64; 4], 0.0);
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if let Some(p) = img.get_pixel_checked(x + dx, y + dy) {
+                            for (c, &v) in p.slice().iter().enumerate() {
+                                sum[c] += v as f64;
+                                sq[c] += v as f64 * v as f64;
+                            }
+                            n += 1.0;
+                        }
+                    }
+                }
+
+                let m = mean.pixel_slice_mut(x as usize, y as usize);
+                for c in 0..channels {
+                    m[c] = (sum[c] / n) as f32;
+                }
+                let v = variance.pixel_slice_mut(x as usize, y as usize);
+                for c in 0..channels {
+                    v[c] = (sq[c] / n - (sum[c] / n).powi(2)).max(0.0) as f32;
+                }
+            }
+        }
+
+        (mean, variance)
+    }
+
+    #[test]
+    fn windows_match_the_border_mode() {
+        let modes = [BorderMode::Wrap, BorderMode::Clamp, BorderMode::Mirror, BorderMode::Skip, BorderMode::Constant(Pixel::rgba(0.5, 2.0, -1.0, 0.25))];
+
+        for (w, h) in [(5, 4), (1, 3), (2, 2)] {
+            for mode in modes.iter() {
+                for format in [PixelFormat::Mono, PixelFormat::RGBA] {
+                    let mut img = noise(w, h, format);
+                    img.set_border_mode(mode.clone());
+
+                    // radii past the image size wrap and reflect several times
+                    for radius in [0, 1, 3, 11] {
+                        let (mean, variance) = brute_force(&img, radius);
+                        let close = |a: &FImage, b: &FImage| a.data().iter().zip(b.data()).all(|(x, y)| (x - y).abs() < 1e-4);
+
+                        assert!(close(&img.integral_box_blur(radius), &mean), "{:?} {}x{} radius {}", mode, w, h, radius);
+                        let (m, v) = img.local_mean_variance(radius);
+                        assert!(close(&m, &mean) && close(&v, &variance), "{:?} {}x{} radius {}", mode, w, h, radius);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn variance_is_stable_with_a_large_mean() {
+        let mut img = FImage::new(8, 8, PixelFormat::Mono);
+        for (x, y, px) in img.enumerate_pixels_mut() {
+            px[0] = 1e7 + ((x + y) % 2) as f32;
+        }
+
+        // every 3x3 window of the checkerboard has 5 of one value and 4 of the other
+        let (mean, variance) = img.local_mean_variance(1);
+        assert!(variance.data().iter().all(|v| (v - 20.0 / 81.0).abs() < 1e-6), "{:?}", &variance.data()[..4]);
+        assert!(mean.data().iter().all(|m| (m - 1e7).abs() <= 1.0));
+    }
+
+    #[test]
+    fn huge_radii() {
+        let mut img = noise(3, 2, PixelFormat::RGB);
+        img.set_border_mode(BorderMode::Clamp);
+
+        // a window this large is all border, every pixel ends up at the edge weighted mean
+        let blurred = img.integral_box_blur(1 << 20);
+        let corners = [img.get_pixel(0, 0), img.get_pixel(2, 0), img.get_pixel(0, 1), img.get_pixel(2, 1)];
+        let expected = corners.iter().map(|p| p.r()).sum::<f32>() / 4.0;
+        assert!(blurred.data().iter().step_by(3).all(|v| (v - expected).abs() < 1e-3));
+
+        assert!(img.integral_box_blur(usize::MAX).data().iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn rect_sums() {
+        let img = noise(6, 4, PixelFormat::RGB);
+        let table = img.integral_image();
+
+        let rect = Rect::new(1, 1, 3, 2);
+        let mut expected = [0.0; 3];
+        for y in 1..3 {
+            for x in 1..4 {
+                for (e, v) in expected.iter_mut().zip(img.pixel_slice(x, y)) {
+                    *e += v;
+                }
+            }
+        }
+
+        let sum = table.sum(rect);
+        assert!(sum.slice().iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-5));
+        assert!((table.mean(rect).g() - expected[1] / 6.0).abs() < 1e-6);
+        assert!(table.mean(Rect::new(2, 2, 0, 0)).r().is_nan());
+        assert!(table.try_sum(Rect::new(5, 0, 2, 1)).is_err());
+    }
+}
@@ -8,8 +8,6 @@ pub struct Circle {
 }
 
 struct CircleGrid {
-    width: usize,
-    height: usize,
     grid_width: usize,
     grid_height: usize,
     grid_size: usize,
@@ -20,17 +18,13 @@ impl CircleGrid {
     pub fn new(width: usize, height: usize, grid_size: usize) -> CircleGrid {
         let grid_width = (width as f32 / grid_size as f32).ceil() as usize;
         let grid_height = (height as f32 / grid_size as f32).ceil() as usize;
-        CircleGrid { width, height, grid_width, grid_height, grid_size, data: vec![-1.0; grid_width * grid_height].into_boxed_slice() }
+        CircleGrid { grid_width, grid_height, grid_size, data: vec![-1.0; grid_width * grid_height].into_boxed_slice() }
     }
 
     pub fn get(&self, x: usize, y: usize) -> f32 {
         self.data[x / self.grid_size + (y / self.grid_size) * self.grid_width]
     }
 
-    pub fn set(&mut self, x: usize, y: usize, value: f32) {
-        self.data[x / self.grid_size + (y / self.grid_size) * self.grid_width] = value;
-    }
-
     pub fn set_grid(&mut self, gx: usize, gy: usize, value: f32) {
         self.data[gx + gy * self.grid_width] = value;
     }
@@ -50,14 +44,6 @@ impl CircleGrid {
     pub fn grid_size(&self) -> usize {
         self.grid_size
     }
-
-    pub fn width(&self) -> usize {
-        self.width
-    }
-
-    pub fn height(&self) -> usize {
-        self.height
-    }
 }
 
 fn pick_point(grid: &CircleGrid, set: &HashSet<(usize, usize)>) -> (usize, usize) {
@@ -94,7 +80,7 @@ pub fn generate_circles(width: usize, height: usize, min_radius: usize, max_radi
     while area < total_area * coverage {
         let mut max = max_radius;
 
-        if available_spaces.len() == 0 {
+        if available_spaces.is_empty() {
             println!("Ran out of space!");
             break;
         }
@@ -116,8 +102,7 @@ pub fn generate_circles(width: usize, height: usize, min_radius: usize, max_radi
 
         let mut visited = HashSet::new();
         let mut q = vec![start_grid];
-        while q.len() > 0 {
-            let (px, py) = q.pop().unwrap();
+        while let Some((px, py)) = q.pop() {
             if !visited.contains(&(px, py)) {
                 visited.insert((px, py));
                 let rpx = (px as f32 + 0.5) * grid.grid_size() as f32;
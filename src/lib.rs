@@ -0,0 +1,25 @@
+pub mod error;
+pub mod image_filter;
+pub mod float_image;
+pub mod image_view;
+pub mod color_space;
+pub mod compositing;
+pub mod blend_mode;
+pub mod transform;
+pub mod sampler;
+pub mod image_io;
+pub mod tone_map;
+pub mod dither;
+pub mod histogram;
+pub mod normalize;
+pub mod statistics;
+pub mod channels;
+pub mod arithmetic;
+pub mod native_format;
+pub mod palette;
+pub mod pyramid;
+pub mod integral;
+pub mod circle_drawer;
+pub mod ishihara_generator;
+
+pub use error::{Error, Result};
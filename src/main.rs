@@ -1,26 +1,21 @@
-mod image_filter;
-mod float_image;
-mod circle_drawer;
-mod ishihara_generator;
+use image_processing::{image_filter, float_image::{FImage, PixelFormat, Pixel}, ishihara_generator::generate_circles, circle_drawer::fill_circle};
 
-use image::{io::Reader as ImageReader, ImageBuffer, RgbImage, RgbaImage};
-use image_filter::{FilterMatrix};
+const BG_COLORS: [&str; 7] = ["#cf5f47", "#cf5f47", "#fd9500", "#ffd500", "#ee8568", "#ee8568", "#eebd7a"];
 
-use crate::{float_image::{FImage, PixelFormat, Pixel}, ishihara_generator::generate_circles, circle_drawer::{draw_circle, fill_circle}};
-
-const BG_COLORS: [&'static str; 7] = ["#cf5f47", "#cf5f47", "#fd9500", "#ffd500", "#ee8568", "#ee8568", "#eebd7a"];
-
-const FG_COLORS: [&'static str; 3] = ["#5a8a50", "#a2ab5a", "#c9cc7d"];
+const FG_COLORS: [&str; 3] = ["#5a8a50", "#a2ab5a", "#c9cc7d"];
 
+#[allow(dead_code)]
 const GRADIENT_H: [[f32; 3]; 3] = [[-1.0, 0.0, 1.0],
                                [-2.0, 0.0, 2.0],
                                [-1.0, 0.0, 1.0]];
 
+#[allow(dead_code)]
 const GRADIENT_V: [[f32; 3]; 3] = [[1.0, 2.0, 1.0],
                                [0.0, 0.0, 0.0],
                                [-1.0, -2.0, -1.0]];
 
 
+#[allow(dead_code)]
 const GAUSSIAN: [[f32; 9]; 9] =   [[0.0000, 0.0000, 0.0000, 0.0001, 0.0001, 0.0001, 0.0000, 0.0000, 0.0000],
                                [0.0000,	0.0000,	0.0004,	0.0014,	0.0023,	0.0014,	0.0004,	0.0000,	0.0000],
                                [0.0000,	0.0004,	0.0037,	0.0146,	0.0232,	0.0146,	0.0037,	0.0004,	0.0000],
@@ -31,13 +26,12 @@ const GAUSSIAN: [[f32; 9]; 9] =   [[0.0000, 0.0000, 0.0000, 0.0001, 0.0001, 0.00
                                [0.0000,	0.0000,	0.0004,	0.0014,	0.0023,	0.0014,	0.0004,	0.0000,	0.0000],
                                [0.0000,	0.0000,	0.0000,	0.0001,	0.0001,	0.0001,	0.0000,	0.0000,	0.0000]];
 fn main() {
-    let input = ImageReader::open("input.png").unwrap().decode().unwrap();
-    let mut fimage = FImage::new(input.width() as usize, input.height() as usize, PixelFormat::RGBA);
-    fimage.copy_from_image_buffer(&input);
+    // circles are picked by the input's alpha, Pixel::a reads 1.0 for images without one
+    let fimage = FImage::open("input.png").unwrap();
 
     let mut ishihara_canvas = FImage::new(fimage.width(), fimage.height(), PixelFormat::RGBA);
     // fill bg white
-    ishihara_canvas = image_filter::fn_filter(&mut ishihara_canvas, |_, _, _| Pixel::rgba(1.0, 1.0, 1.0, 1.0));
+    ishihara_canvas = image_filter::fn_filter(&ishihara_canvas, |_, _, _| Pixel::rgba(1.0, 1.0, 1.0, 1.0));
     // println!("Blurring...");
     // let blurred = image_filter::filter_image(&fimage, FilterMatrix::new(GAUSSIAN));
 
@@ -46,18 +40,8 @@ fn main() {
     // let v = image_filter::filter_image(&blurred, FilterMatrix::new(GRADIENT_V));
 
     // // square and invert
-    // let h_sqinv = image_filter::fn_filter(&h, |x, y, p| {
-    //     let t = Pixel::rgba(-1.0 * (p.r() * p.r()), -1.0 * (p.g() * p.g()), -1.0 * (p.b() * p.b()), p.a());
-    //     let v = t.slice()[..p.slice().len()].to_owned();
-
-    //     Pixel::from_boxed_slice(v.into_boxed_slice())
-    // });
-    // let v_sqinv = image_filter::fn_filter(&v, |x, y, p| {
-    //     let t = Pixel::rgba(-1.0 * (p.r() * p.r()), -1.0 * (p.g() * p.g()), -1.0 * (p.b() * p.b()), p.a());
-    //     let v = t.slice()[..p.slice().len()].to_owned();
-
-    //     Pixel::from_boxed_slice(v.into_boxed_slice())
-    // });
+    // let h_sqinv = -(&h * &h);
+    // let v_sqinv = -(&v * &v);
 
     // let combined = image_filter::combine_images(&h_sqinv, &v_sqinv);
     // let mut mono = image_filter::combine_color_channels(&combined);
@@ -79,17 +63,14 @@ fn main() {
         let cat_pixel = fimage.get_pixel(circle.x as i32, circle.y as i32);
         let color = if cat_pixel.a() > 0.2 {
             let r = rand::random::<usize>() % fgs.len();
-            fgs[r].clone()
+            fgs[r]
         } else {
             let r = rand::random::<usize>() % bgs.len();
-            bgs[r].clone()
+            bgs[r]
         };
         fill_circle(circle.x as i32, circle.y as i32, circle.radius as i32, color, false, &mut ishihara_canvas);
     }
 
-    let mut output = RgbaImage::new(input.width(), input.height());
-    ishihara_canvas.copy_to_image_buffer(&mut output);
-
     println!("Done.");
-    output.save("output.png").unwrap();
+    ishihara_canvas.save("output.png").unwrap();
 }
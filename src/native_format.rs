@@ -0,0 +1,385 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crc32fast::Hasher;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::color_space::ColorSpace;
+use crate::error::{Error, Result};
+use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+
+// Lossless binary format for FImage, meant for caching intermediate results. Everything is little endian:
+//
+//   0  magic "FIMG"
+//   4  version u16
+//   6  pixel format u8, color space u8
+//   8  width u64, height u64
+//  24  border mode u8, border constant format u8, compression u8, reserved u8
+//  28  border constant 4 x f32
+//  44  payload length u64
+//  52  crc32 of bytes 0..52 and the uncompressed pixel data
+//  56  payload
+//
+// The payload is the pixel data as f32s. Deflated payloads split the floats into byte planes first,
+// which compresses a lot better than interleaved bytes.
+
+const MAGIC: &[u8; 4] = b"FIMG";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 56;
+const CHECKSUM_OFFSET: usize = 52;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    // zlib level from 0 (fastest) to 9 (smallest)
+    Deflate { level: u32 }
+}
+
+fn format_tag(format: PixelFormat) -> u8 {
+    match format { PixelFormat::Mono => 0, PixelFormat::RGB => 1, PixelFormat::RGBA => 2 }
+}
+
+fn format_from_tag(tag: u8) -> Option<PixelFormat> {
+    match tag { 0 => Some(PixelFormat::Mono), 1 => Some(PixelFormat::RGB), 2 => Some(PixelFormat::RGBA), _ => None }
+}
+
+const COLOR_SPACES: [ColorSpace; 9] = [ColorSpace::Srgb, ColorSpace::LinearSrgb, ColorSpace::Hsv, ColorSpace::Hsl, ColorSpace::Xyz,
+                                       ColorSpace::Lab, ColorSpace::Lch, ColorSpace::Luv, ColorSpace::YCbCr];
+
+fn color_space_tag(color_space: ColorSpace) -> u8 {
+    COLOR_SPACES.iter().position(|&c| c == color_space).unwrap_or(0) as u8
+}
+
+// (tag, constant pixel) of a border mode
+fn border_tag(border: &BorderMode) -> (u8, Option<&Pixel<'static>>) {
+    match border {
+        BorderMode::Wrap => (0, None),
+        BorderMode::Clamp => (1, None),
+        BorderMode::Mirror => (2, None),
+        BorderMode::Constant(pixel) => (3, Some(pixel)),
+        BorderMode::Skip => (4, None)
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::Codec(format!("invalid native image: {}", msg))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+impl FImage {
+    fn native_header(&self, compression: Compression, payload_len: usize) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        let (border, constant) = border_tag(self.border_mode());
+
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6] = format_tag(self.get_pixel_format());
+        header[7] = color_space_tag(self.color_space());
+        header[8..16].copy_from_slice(&(self.width() as u64).to_le_bytes());
+        header[16..24].copy_from_slice(&(self.height() as u64).to_le_bytes());
+        header[24] = border;
+        header[26] = match compression { Compression::None => 0, Compression::Deflate { .. } => 1 };
+        if let Some(pixel) = constant {
+            header[25] = format_tag(pixel.format());
+            for (i, v) in pixel.slice().iter().enumerate() {
+                header[28 + i * 4..32 + i * 4].copy_from_slice(&v.to_le_bytes());
+            }
+        }
+        header[44..52].copy_from_slice(&(payload_len as u64).to_le_bytes());
+
+        header
+    }
+
+    pub fn write_native<W: Write>(&self, writer: &mut W, compression: Compression) -> Result<()> {
+        let raw: Vec<u8> = self.data().iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let deflated = match compression {
+            Compression::None => None,
+            Compression::Deflate { level } => {
+                let mut planes = Vec::with_capacity(raw.len());
+                for plane in 0..4 {
+                    planes.extend(raw.iter().skip(plane).step_by(4));
+                }
+
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+                encoder.write_all(&planes)?;
+                Some(encoder.finish()?)
+            }
+        };
+        let payload = deflated.as_deref().unwrap_or(&raw);
+
+        let mut header = self.native_header(compression, payload.len());
+        let mut hasher = Hasher::new();
+        hasher.update(&header[..CHECKSUM_OFFSET]);
+        hasher.update(&raw);
+        header[CHECKSUM_OFFSET..].copy_from_slice(&hasher.finalize().to_le_bytes());
+
+        writer.write_all(&header)?;
+        writer.write_all(payload)?;
+
+        Ok(())
+    }
+
+    pub fn read_native<R: Read>(reader: &mut R) -> Result<FImage> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        if &header[0..4] != MAGIC {
+            return Err(invalid("unknown magic"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let format = format_from_tag(header[6]).ok_or_else(|| invalid("unknown pixel format"))?;
+        let color_space = *COLOR_SPACES.get(header[7] as usize).ok_or_else(|| invalid("unknown color space"))?;
+        let width = usize::try_from(read_u64(&header, 8)).map_err(|_| invalid("width too large"))?;
+        let height = usize::try_from(read_u64(&header, 16)).map_err(|_| invalid("height too large"))?;
+        let len = width.checked_mul(height).and_then(|n| n.checked_mul(format.channel_count() * 4))
+            .ok_or_else(|| invalid("dimensions too large"))?;
+
+        let border = match header[24] {
+            0 => BorderMode::Wrap,
+            1 => BorderMode::Clamp,
+            2 => BorderMode::Mirror,
+            3 => {
+                let constant = format_from_tag(header[25]).ok_or_else(|| invalid("unknown border constant format"))?;
+                let mut values = [0.0; 4];
+                for (i, v) in values.iter_mut().enumerate() {
+                    *v = read_f32(&header, 28 + i * 4);
+                }
+                BorderMode::Constant(Pixel::from_array(values, constant))
+            },
+            4 => BorderMode::Skip,
+            _ => return Err(invalid("unknown border mode"))
+        };
+
+        // read through take so a corrupt length can't allocate more than the stream holds
+        let payload_len = read_u64(&header, 44);
+        let mut payload = Vec::new();
+        reader.take(payload_len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < payload_len {
+            return Err(invalid("truncated payload"));
+        }
+
+        let raw = match header[26] {
+            0 => payload,
+            1 => {
+                let mut planes = Vec::new();
+                ZlibDecoder::new(&payload[..]).take(len as u64 + 1).read_to_end(&mut planes)
+                    .map_err(|e| invalid(&e.to_string()))?;
+                if planes.len() != len {
+                    return Err(invalid("wrong payload size"));
+                }
+
+                let n = len / 4;
+                let mut raw = vec![0; len];
+                for (i, b) in raw.iter_mut().enumerate() {
+                    *b = planes[(i % 4) * n + i / 4];
+                }
+                raw
+            },
+            _ => return Err(invalid("unknown compression"))
+        };
+        if raw.len() != len {
+            return Err(invalid("wrong payload size"));
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update(&header[..CHECKSUM_OFFSET]);
+        hasher.update(&raw);
+        let expected = u32::from_le_bytes(header[CHECKSUM_OFFSET..].try_into().unwrap());
+        let found = hasher.finalize();
+        if expected != found {
+            return Err(Error::ChecksumMismatch { expected, found });
+        }
+
+        let mut out = FImage::new(width, height, format);
+        for (v, b) in out.data_mut().iter_mut().zip(raw.chunks_exact(4)) {
+            *v = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        out.set_color_space(color_space);
+        out.set_border_mode(border);
+
+        Ok(out)
+    }
+
+    pub fn to_native_bytes(&self, compression: Compression) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_native(&mut bytes, compression).unwrap_or_else(|e| panic!("{}", e));
+
+        bytes
+    }
+
+    pub fn from_native_bytes(mut bytes: &[u8]) -> Result<FImage> {
+        FImage::read_native(&mut bytes)
+    }
+
+    pub fn save_native<P: AsRef<Path>>(&self, path: P, compression: Compression) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        self.write_native(&mut file, compression)?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    pub fn open_native<P: AsRef<Path>>(path: P) -> Result<FImage> {
+        FImage::read_native(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::color_space::ColorSpace;
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+
+    // pixels are stored as their 1, 3 or 4 values
+    impl Serialize for Pixel<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.slice().serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Pixel<'static> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pixel<'static>, D::Error> {
+            let values = Vec::<f32>::deserialize(deserializer)?;
+
+            Pixel::try_from_boxed_slice(values.into_boxed_slice()).map_err(D::Error::custom)
+        }
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename = "FImage")]
+    struct FImageRef<'a> {
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        color_space: ColorSpace,
+        border: &'a BorderMode,
+        data: &'a [f32]
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "FImage")]
+    struct FImageOwned {
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        color_space: ColorSpace,
+        border: BorderMode,
+        data: Vec<f32>
+    }
+
+    impl Serialize for FImage {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            FImageRef {
+                width: self.width(),
+                height: self.height(),
+                format: self.get_pixel_format(),
+                color_space: self.color_space(),
+                border: self.border_mode(),
+                data: self.data()
+            }.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for FImage {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FImage, D::Error> {
+            let repr = FImageOwned::deserialize(deserializer)?;
+
+            let expected = repr.width.checked_mul(repr.height).and_then(|n| n.checked_mul(repr.format.channel_count()));
+            if expected != Some(repr.data.len()) {
+                return Err(D::Error::custom(format!("{} values don't fill a {}x{} {:?} image", repr.data.len(), repr.width, repr.height, repr.format)));
+            }
+
+            let mut out = FImage::new(repr.width, repr.height, repr.format);
+            out.data_mut().copy_from_slice(&repr.data);
+            out.set_color_space(repr.color_space);
+            out.set_border_mode(repr.border);
+
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, HEADER_SIZE};
+    use crate::color_space::ColorSpace;
+    use crate::error::Error;
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+
+    fn sample() -> FImage {
+        let mut img = FImage::new(7, 5, PixelFormat::RGBA);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = (i as f32 * 0.37).sin() * 3.0;
+        }
+        img.data_mut()[9] = f32::INFINITY;
+        img.set_color_space(ColorSpace::Lab);
+        img.set_border_mode(BorderMode::Constant(Pixel::rgb(0.25, -1.0, 8.0)));
+
+        img
+    }
+
+    #[test]
+    fn round_trips_exactly() {
+        let img = sample();
+
+        for compression in [Compression::None, Compression::Deflate { level: 0 }, Compression::Deflate { level: 9 }] {
+            let back = FImage::from_native_bytes(&img.to_native_bytes(compression)).unwrap();
+
+            assert_eq!(back.get_pixel_format(), PixelFormat::RGBA);
+            assert_eq!((back.width(), back.height(), back.color_space()), (7, 5, ColorSpace::Lab));
+            assert!(back.data().iter().zip(img.data()).all(|(a, b)| a.to_bits() == b.to_bits()), "{:?}", compression);
+            match back.border_mode() {
+                BorderMode::Constant(p) => assert_eq!(p.slice(), &[0.25, -1.0, 8.0]),
+                other => panic!("border came back as {:?}", other)
+            }
+        }
+
+        let empty = FImage::new(0, 3, PixelFormat::Mono);
+        assert_eq!(FImage::from_native_bytes(&empty.to_native_bytes(Compression::Deflate { level: 6 })).unwrap().height(), 3);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let bytes = sample().to_native_bytes(Compression::None);
+
+        let mut flipped = bytes.clone();
+        flipped[HEADER_SIZE + 20] ^= 1;
+        assert!(matches!(FImage::from_native_bytes(&flipped), Err(Error::ChecksumMismatch { .. })));
+
+        let mut versioned = bytes.clone();
+        versioned[4] = 9;
+        assert!(matches!(FImage::from_native_bytes(&versioned), Err(Error::UnsupportedVersion(9))));
+
+        assert!(FImage::from_native_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(FImage::from_native_bytes(&bytes[..20]).is_err());
+        assert!(matches!(FImage::from_native_bytes(b"NOPE"), Err(Error::Io(_))));
+
+        // a huge size in the header fails instead of allocating
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(FImage::from_native_bytes(&huge), Err(Error::Codec(_))));
+
+        let compressed = sample().to_native_bytes(Compression::Deflate { level: 6 });
+        let mut bad = compressed.clone();
+        let last = bad.len() - 8;
+        bad[last] ^= 0xff;
+        assert!(FImage::from_native_bytes(&bad).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::float_image::FImage;
+use crate::histogram::value_range;
+use crate::statistics::quantile;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalizeMode {
+    // one min/max stretch shared by all color channels
+    Global,
+    PerChannel,
+    // stretches the low and high percentiles (0 to 100) of every channel to [0, 1], e.g. 1 and 99
+    Percentile { low: f32, high: f32 },
+    // zero mean and unit standard deviation per channel, values aren't clipped
+    Standardize
+}
+
+// the map applied to a channel, v -> (v - offset) / scale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelNormalization {
+    pub offset: f32,
+    pub scale: f32
+}
+
+impl ChannelNormalization {
+    // a zero scale (a flat channel) is replaced by 1 so values only get shifted
+    pub fn new(offset: f32, scale: f32) -> ChannelNormalization {
+        ChannelNormalization { offset, scale: if scale == 0.0 || !scale.is_finite() { 1.0 } else { scale } }
+    }
+
+    pub fn apply(&self, v: f32) -> f32 {
+        (v - self.offset) / self.scale
+    }
+}
+
+impl FImage {
+    // stretches the color channels together to [0, 1], see normalize_with
+    pub fn normalize(&mut self) -> Vec<ChannelNormalization> {
+        self.normalize_with(NormalizeMode::Global)
+    }
+
+    // Normalizes the color channels and returns the map used for each of them. Alpha is left alone
+    // and NaNs are ignored when gathering statistics, channels with no other values are left unchanged.
+    pub fn normalize_with(&mut self, mode: NormalizeMode) -> Vec<ChannelNormalization> {
+        let channels = self.get_pixel_format().channel_count();
+        let color = channels.min(3);
+
+        let channel_values = |img: &FImage, c: usize| -> Vec<f32> {
+            img.data().iter().skip(c).step_by(channels).copied().filter(|v| !v.is_nan()).collect()
+        };
+
+        let maps: Vec<ChannelNormalization> = match mode {
+            NormalizeMode::Global => {
+                let (min, max) = value_range(self.data().iter().enumerate().filter(|(i, _)| i % channels < color).map(|(_, v)| v));
+                vec![ChannelNormalization::new(min, max - min); color]
+            },
+            NormalizeMode::PerChannel => (0..color).map(|c| {
+                let (min, max) = value_range(self.data().iter().skip(c).step_by(channels));
+                ChannelNormalization::new(min, max - min)
+            }).collect(),
+            NormalizeMode::Percentile { low, high } => (0..color).map(|c| {
+                let mut values = channel_values(self, c);
+                if values.is_empty() {
+                    return ChannelNormalization::new(0.0, 1.0);
+                }
+                let (lo, hi) = (quantile(&mut values, low / 100.0), quantile(&mut values, high / 100.0));
+                ChannelNormalization::new(lo, hi - lo)
+            }).collect(),
+            NormalizeMode::Standardize => self.statistics()[..color].iter().map(|s| {
+                if s.count == 0 { ChannelNormalization::new(0.0, 1.0) } else { ChannelNormalization::new(s.mean, s.std_dev()) }
+            }).collect()
+        };
+
+        let clip = mode != NormalizeMode::Standardize;
+        for px in self.data_mut().chunks_exact_mut(channels) {
+            for (v, map) in px.iter_mut().zip(&maps) {
+                *v = if clip { map.apply(*v).clamp(0.0, 1.0) } else { map.apply(*v) };
+            }
+        }
+
+        maps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelNormalization, NormalizeMode};
+    use crate::float_image::{FImage, PixelFormat};
+
+    fn image(values: &[[f32; 4]]) -> FImage {
+        let mut img = FImage::new(values.len(), 1, PixelFormat::RGBA);
+        for (px, v) in img.data_mut().chunks_exact_mut(4).zip(values) {
+            px.copy_from_slice(v);
+        }
+
+        img
+    }
+
+    #[test]
+    fn stretches() {
+        let values = [[1.0, 2.0, 2.0, 3.0], [3.0, 4.0, 2.0, 7.0], [2.0, 3.0, 2.0, 0.5]];
+
+        let mut img = image(&values);
+        let maps = img.normalize();
+        assert_eq!(maps, vec![ChannelNormalization { offset: 1.0, scale: 3.0 }; 3]);
+        assert_eq!(img.get_pixel(1, 0).slice(), &[2.0 / 3.0, 1.0, 1.0 / 3.0, 7.0]);
+
+        // a flat channel is only shifted
+        let mut img = image(&values);
+        let maps = img.normalize_with(NormalizeMode::PerChannel);
+        assert_eq!(maps[2], ChannelNormalization { offset: 2.0, scale: 1.0 });
+        assert_eq!(img.get_pixel(2, 0).slice(), &[0.5, 0.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn percentiles_clip_outliers() {
+        let mut img = FImage::new(101, 1, PixelFormat::Mono);
+        img.data_mut().iter_mut().enumerate().for_each(|(i, v)| *v = i as f32);
+        img.data_mut()[100] = 1000.0;
+        img.data_mut()[3] = f32::NAN;
+
+        let maps = img.normalize_with(NormalizeMode::Percentile { low: 10.0, high: 90.0 });
+        // 100 values left, ranks 9.9 and 89.1 of 0, 1, 2, 4, .., 99, 1000
+        assert!((maps[0].offset - 10.9).abs() < 1e-4 && (maps[0].scale - 79.2).abs() < 1e-4, "{:?}", maps);
+        assert_eq!((img.data()[0], img.data()[100]), (0.0, 1.0));
+        assert!((img.data()[50] - 39.1 / 79.2).abs() < 1e-5 && img.data()[3].is_nan());
+    }
+
+    #[test]
+    fn standardize() {
+        let mut img = image(&[[1.0, 5.0, 0.0, 0.2], [3.0, 5.0, 0.0, 0.2], [5.0, 5.0, 0.0, 0.2], [7.0, 5.0, 0.0, 0.2]]);
+        let maps = img.normalize_with(NormalizeMode::Standardize);
+
+        assert_eq!(maps[0].offset, 4.0);
+        assert!((maps[0].scale - 5.0f32.sqrt()).abs() < 1e-6);
+        let r: Vec<f32> = img.data().iter().step_by(4).copied().collect();
+        assert!((r.iter().sum::<f32>()).abs() < 1e-5 && (r.iter().map(|v| v * v).sum::<f32>() / 4.0 - 1.0).abs() < 1e-5);
+        assert_eq!(img.get_pixel(3, 0).slice()[1..], [0.0, 0.0, 0.2]);
+    }
+
+    #[test]
+    fn channels_without_values_are_left_alone() {
+        for mode in [NormalizeMode::Global, NormalizeMode::PerChannel, NormalizeMode::Percentile { low: 1.0, high: 99.0 }, NormalizeMode::Standardize] {
+            let mut img = FImage::new(3, 1, PixelFormat::Mono);
+            img.data_mut().fill(f32::NAN);
+
+            assert_eq!(img.normalize_with(mode), vec![ChannelNormalization { offset: 0.0, scale: 1.0 }], "{:?}", mode);
+        }
+    }
+}
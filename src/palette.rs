@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::color_space::{convert, ColorSpace};
+use crate::dither::Dither;
+use crate::float_image::{FImage, Pixel, PixelFormat};
+
+// Palette colors are sRGB, whatever the color space of the image they come from or are applied to.
+// Pixels are clamped to [0, 1] and fully transparent pixels are left out of the palette.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizeMethod {
+    // splits the box of colors with the largest error at its median, in Lab
+    MedianCut,
+    // merges the least populated leaves of an 8 level RGB octree
+    Octree,
+    // Lloyd iterations from a k-means++ start, with Lab distances. The seed makes runs repeatable.
+    KMeans { iterations: usize, seed: u64 }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PaletteEntry {
+    pub color: Pixel<'static>,
+    // number of pixels the entry stands for
+    pub count: usize
+}
+
+// entries are sorted by count, most common first
+#[derive(Debug, Clone)]
+pub struct Palette {
+    entries: Vec<PaletteEntry>,
+    lab: Vec<[f32; 3]>
+}
+
+// unique color at 8 bit precision, with the number of pixels that have it
+struct Sample {
+    rgb: [f32; 3],
+    lab: [f32; 3],
+    weight: usize
+}
+
+fn distance_sq(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn nearest(centers: &[[f32; 3]], c: &[f32; 3]) -> usize {
+    centers.iter().enumerate().min_by(|(_, a), (_, b)| distance_sq(a, c).total_cmp(&distance_sq(b, c))).map(|(i, _)| i).unwrap_or(0)
+}
+
+// weighted mean of the samples in Lab
+fn mean_lab(samples: &[&Sample]) -> [f32; 3] {
+    let total: f64 = samples.iter().map(|s| s.weight as f64).sum();
+    let mut sum = [0.0f64; 3];
+    for s in samples {
+        for (acc, v) in sum.iter_mut().zip(s.lab) {
+            *acc += v as f64 * s.weight as f64;
+        }
+    }
+
+    sum.map(|v| (v / total.max(1.0)) as f32)
+}
+
+fn lab_to_entry(lab: [f32; 3], count: usize) -> PaletteEntry {
+    let c = convert(lab, ColorSpace::Lab, ColorSpace::Srgb).map(|v| v.clamp(0.0, 1.0));
+
+    PaletteEntry { color: Pixel::rgb(c[0], c[1], c[2]), count }
+}
+
+fn median_cut(samples: &[Sample], colors: usize) -> Vec<PaletteEntry> {
+    // summed squared distance from the mean, weighted by population
+    let error = |b: &[&Sample]| {
+        let mean = mean_lab(b);
+        b.iter().map(|s| distance_sq(&s.lab, &mean) as f64 * s.weight as f64).sum::<f64>()
+    };
+
+    let all: Vec<&Sample> = samples.iter().collect();
+    let mut boxes = vec![(error(&all), all)];
+    while boxes.len() < colors {
+        let Some((index, _)) = boxes.iter().enumerate().filter(|(_, (_, b))| b.len() > 1)
+            .max_by(|a, b| a.1.0.total_cmp(&b.1.0)) else { break };
+
+        let (_, mut b) = boxes.swap_remove(index);
+        let axis = (0..3).max_by(|&i, &j| {
+            let span = |axis: usize| {
+                let (lo, hi) = b.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), s| (lo.min(s.lab[axis]), hi.max(s.lab[axis])));
+                hi - lo
+            };
+            span(i).total_cmp(&span(j))
+        }).unwrap_or(0);
+        b.sort_by(|s, t| s.lab[axis].total_cmp(&t.lab[axis]));
+
+        // split where half the population lies on either side, keeping both halves non empty
+        let total: usize = b.iter().map(|s| s.weight).sum();
+        let mut acc = 0;
+        let mut split = 1;
+        for (i, s) in b.iter().enumerate() {
+            acc += s.weight;
+            if acc * 2 >= total {
+                split = (i + 1).clamp(1, b.len() - 1);
+                break;
+            }
+        }
+
+        let upper = b.split_off(split);
+        boxes.push((error(&b), b));
+        boxes.push((error(&upper), upper));
+    }
+
+    boxes.iter().map(|(_, b)| lab_to_entry(mean_lab(b), b.iter().map(|s| s.weight).sum())).collect()
+}
+
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    sum: [f64; 3],
+    count: usize,
+    leaf: bool
+}
+
+impl OctreeNode {
+    fn new() -> OctreeNode {
+        OctreeNode { children: [None; 8], sum: [0.0; 3], count: 0, leaf: false }
+    }
+}
+
+fn octree(samples: &[Sample], colors: usize) -> Vec<PaletteEntry> {
+    const DEPTH: usize = 8;
+
+    // every node keeps the totals of its subtree so reducing one only has to drop its children
+    let mut nodes = vec![OctreeNode::new()];
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); DEPTH];
+    let mut leaves = 0;
+
+    for s in samples {
+        let rgb = s.rgb.map(|v| (v * 255.0).round() as u8);
+        let mut node = 0;
+
+        for level in 0..=DEPTH {
+            let n = &mut nodes[node];
+            n.count += s.weight;
+            for (acc, v) in n.sum.iter_mut().zip(s.rgb) {
+                *acc += v as f64 * s.weight as f64;
+            }
+            if level == DEPTH {
+                break;
+            }
+
+            let shift = 7 - level;
+            let octant = (((rgb[0] >> shift) & 1) << 2 | ((rgb[1] >> shift) & 1) << 1 | ((rgb[2] >> shift) & 1)) as usize;
+            node = match nodes[node].children[octant] {
+                Some(child) => child,
+                None => {
+                    let child = nodes.len();
+                    let mut new = OctreeNode::new();
+                    new.leaf = level + 1 == DEPTH;
+                    if new.leaf {
+                        leaves += 1;
+                    } else {
+                        levels[level + 1].push(child);
+                    }
+                    nodes.push(new);
+                    nodes[node].children[octant] = Some(child);
+                    child
+                }
+            };
+        }
+    }
+    levels[0].push(0);
+
+    // Folds the least populated node of the deepest level into a leaf, its children are all leaves by then.
+    // Subtree counts never change, so each level is sorted once with the smallest nodes at the end.
+    for level in levels.iter_mut() {
+        level.sort_by_key(|&n| std::cmp::Reverse(nodes[n].count));
+    }
+    while leaves > colors {
+        let Some(node) = levels.iter_mut().rev().find_map(|l| l.pop()) else { break };
+
+        let mut children: Vec<usize> = nodes[node].children.iter().flatten().copied().collect();
+        if leaves + 1 - children.len() >= colors {
+            nodes[node].children = [None; 8];
+            nodes[node].leaf = true;
+            leaves = leaves + 1 - children.len();
+            continue;
+        }
+
+        // folding all of them would leave too few colors, merge the smallest children into their closest siblings instead
+        children.sort_by_key(|&c| std::cmp::Reverse(nodes[c].count));
+        while leaves > colors {
+            let smallest = children.pop().unwrap();
+            let mean = |n: &OctreeNode| n.sum.map(|v| (v / n.count as f64) as f32);
+            let target = children[nearest(&children.iter().map(|&c| mean(&nodes[c])).collect::<Vec<_>>(), &mean(&nodes[smallest]))];
+
+            let (sum, count) = (nodes[smallest].sum, nodes[smallest].count);
+            for (acc, v) in nodes[target].sum.iter_mut().zip(sum) {
+                *acc += v;
+            }
+            nodes[target].count += count;
+            for slot in nodes[node].children.iter_mut().filter(|c| **c == Some(smallest)) {
+                *slot = None;
+            }
+            leaves -= 1;
+        }
+    }
+
+    let mut entries = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let n = &nodes[node];
+        if n.leaf {
+            let c = n.sum.map(|v| (v / n.count as f64) as f32);
+            entries.push(PaletteEntry { color: Pixel::rgb(c[0], c[1], c[2]), count: n.count });
+        } else {
+            stack.extend(n.children.iter().flatten());
+        }
+    }
+
+    entries
+}
+
+fn k_means(samples: &[Sample], colors: usize, iterations: usize, seed: u64) -> Vec<PaletteEntry> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let total: usize = samples.iter().map(|s| s.weight).sum();
+
+    // k-means++, the first center is drawn by population and the others by population times squared
+    // distance to the nearest center so far
+    let mut centers = Vec::with_capacity(colors);
+    let mut pick = rng.gen_range(0..total);
+    centers.push(samples.iter().find(|s| {
+        let hit = pick < s.weight;
+        pick = pick.saturating_sub(s.weight);
+        hit
+    }).map(|s| s.lab).unwrap_or(samples[0].lab));
+
+    let mut dist: Vec<f32> = samples.iter().map(|s| distance_sq(&s.lab, &centers[0])).collect();
+    while centers.len() < colors.min(samples.len()) {
+        let weights: Vec<f64> = samples.iter().zip(&dist).map(|(s, &d)| s.weight as f64 * d as f64).collect();
+        let sum: f64 = weights.iter().sum();
+        if sum <= 0.0 {
+            break;
+        }
+
+        let mut pick = rng.gen::<f64>() * sum;
+        let index = weights.iter().position(|&w| {
+            pick -= w;
+            pick < 0.0
+        }).unwrap_or(samples.len() - 1);
+
+        let center = samples[index].lab;
+        for (d, s) in dist.iter_mut().zip(samples) {
+            *d = d.min(distance_sq(&s.lab, &center));
+        }
+        centers.push(center);
+    }
+
+    let mut assignment = vec![usize::MAX; samples.len()];
+    for _ in 0..iterations.max(1) {
+        let mut changed = false;
+        for (a, s) in assignment.iter_mut().zip(samples) {
+            let n = nearest(&centers, &s.lab);
+            changed |= *a != n;
+            *a = n;
+        }
+        if !changed {
+            break;
+        }
+
+        let mut members: Vec<Vec<&Sample>> = vec![Vec::new(); centers.len()];
+        for (&a, s) in assignment.iter().zip(samples) {
+            members[a].push(s);
+        }
+        for (center, m) in centers.iter_mut().zip(&members) {
+            if !m.is_empty() {
+                *center = mean_lab(m);
+            }
+        }
+    }
+
+    let mut counts = vec![0; centers.len()];
+    for (s, c) in samples.iter().zip(&assignment) {
+        counts[*c] += s.weight;
+    }
+
+    centers.iter().zip(counts).filter(|(_, count)| *count > 0).map(|(&lab, count)| lab_to_entry(lab, count)).collect()
+}
+
+impl Palette {
+    // palette with the given colors and no counts, e.g. from a list of hex strings
+    pub fn new(colors: &[Pixel]) -> Palette {
+        Palette::from_entries(colors.iter().map(|c| PaletteEntry { color: Pixel::rgb(c.r(), c.g(), c.b()), count: 0 }).collect())
+    }
+
+    fn from_entries(mut entries: Vec<PaletteEntry>) -> Palette {
+        entries.sort_by_key(|e| std::cmp::Reverse(e.count));
+        let lab = entries.iter().map(|e| convert([e.color.r(), e.color.g(), e.color.b()], ColorSpace::Srgb, ColorSpace::Lab)).collect();
+
+        Palette { entries, lab }
+    }
+
+    pub fn entries(&self) -> &[PaletteEntry] {
+        &self.entries
+    }
+
+    pub fn colors(&self) -> Vec<Pixel<'static>> {
+        self.entries.iter().map(|e| e.color).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // index of the entry closest to an sRGB color in Lab
+    pub fn nearest(&self, color: &Pixel) -> usize {
+        self.nearest_rgb([color.r(), color.g(), color.b()])
+    }
+
+    fn nearest_rgb(&self, rgb: [f32; 3]) -> usize {
+        nearest(&self.lab, &convert(rgb, ColorSpace::Srgb, ColorSpace::Lab))
+    }
+
+    // Typical step between neighbouring entries, the amplitude of ordered dithering. Steps are the largest
+    // per channel difference, so black and white are 1 apart like two levels of a single channel.
+    fn spread(&self) -> f32 {
+        if self.len() < 2 {
+            return 0.0;
+        }
+
+        let rgb: Vec<[f32; 3]> = self.entries.iter().map(|e| [e.color.r(), e.color.g(), e.color.b()]).collect();
+        let step = |a: &[f32; 3], b: &[f32; 3]| (0..3).map(|c| (a[c] - b[c]).abs()).fold(0.0, f32::max);
+        let total: f32 = rgb.iter().enumerate().map(|(i, a)| {
+            rgb.iter().enumerate().filter(|&(j, _)| j != i).map(|(_, b)| step(a, b)).fold(f32::INFINITY, f32::min)
+        }).sum();
+
+        total / rgb.len() as f32
+    }
+}
+
+impl FImage {
+    // sRGB color of every pixel, mono pixels as gray
+    fn srgb_colors(&self) -> Vec<[f32; 3]> {
+        let color_space = self.color_space();
+
+        self.pixels().map(|p| convert([p.r(), p.g(), p.b()], color_space, ColorSpace::Srgb)).collect()
+    }
+
+    // at most `colors` entries, fewer if the image doesn't have that many distinct colors
+    pub fn palette(&self, colors: usize, method: QuantizeMethod) -> Palette {
+        let mut unique: HashMap<[u8; 3], ([f32; 3], usize)> = HashMap::new();
+        for (c, p) in self.srgb_colors().into_iter().zip(self.pixels()) {
+            if p.a() <= 0.0 {
+                continue;
+            }
+
+            let c = c.map(|v| if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) });
+            unique.entry(c.map(|v| (v * 255.0).round() as u8)).or_insert((c, 0)).1 += 1;
+        }
+
+        // sorted so the result doesn't depend on the hash map's iteration order
+        let mut keys: Vec<[u8; 3]> = unique.keys().copied().collect();
+        keys.sort_unstable();
+        let samples: Vec<Sample> = keys.iter().map(|k| {
+            let (rgb, weight) = unique[k];
+            Sample { rgb, lab: convert(rgb, ColorSpace::Srgb, ColorSpace::Lab), weight }
+        }).collect();
+
+        if samples.is_empty() || colors == 0 {
+            return Palette::from_entries(Vec::new());
+        }
+
+        Palette::from_entries(match method {
+            QuantizeMethod::MedianCut => median_cut(&samples, colors),
+            QuantizeMethod::Octree => octree(&samples, colors),
+            QuantizeMethod::KMeans { iterations, seed } => k_means(&samples, colors, iterations, seed)
+        })
+    }
+
+    // Replaces every color with the nearest palette entry, in Lab. Error diffusion runs on the sRGB
+    // values, ordered dithering offsets them by the typical distance between entries. Alpha is kept
+    // and mono images get the luma of the chosen entry. An empty palette leaves the image unchanged.
+    pub fn remap_to_palette(&self, palette: &Palette, dither: Dither) -> FImage {
+        let mut out = self.clone();
+        if palette.is_empty() {
+            return out;
+        }
+
+        let (w, h) = (self.width(), self.height());
+        let mut work = self.srgb_colors();
+        let mut chosen = vec![0; w * h];
+
+        if let Some(kernel) = dither.diffusion_kernel() {
+            for y in 0..h {
+                let reverse = y % 2 == 1;
+                for i in 0..w {
+                    let x = if reverse { w - 1 - i } else { i };
+                    let old = work[x + y * w];
+                    let index = palette.nearest_rgb(old);
+                    let new = palette.entries[index].color;
+                    let err = [old[0] - new.r(), old[1] - new.g(), old[2] - new.b()];
+                    chosen[x + y * w] = index;
+
+                    for &(dx, dy, weight) in kernel {
+                        let nx = if reverse { x as i32 - dx } else { x as i32 + dx };
+                        let ny = y + dy as usize;
+                        if nx >= 0 && (nx as usize) < w && ny < h {
+                            for (v, e) in work[nx as usize + ny * w].iter_mut().zip(err) {
+                                *v += e * weight;
+                            }
+                        }
+                    }
+                }
+            }
+        } else if let Some((thresholds, size)) = dither.thresholds() {
+            let spread = palette.spread();
+            for (i, c) in work.iter().enumerate() {
+                let offset = (thresholds[i % w % size + (i / w % size) * size] - 0.5) * spread;
+                chosen[i] = palette.nearest_rgb(c.map(|v| v + offset));
+            }
+        } else {
+            for (i, c) in work.iter().enumerate() {
+                chosen[i] = palette.nearest_rgb(*c);
+            }
+        }
+
+        let color_space = self.color_space();
+        let channels = self.get_pixel_format().channel_count();
+        for (px, &index) in out.data_mut().chunks_exact_mut(channels).zip(&chosen) {
+            let color = palette.entries[index].color;
+            if self.get_pixel_format() == PixelFormat::Mono {
+                let l = color.luma();
+                px[0] = convert([l, l, l], ColorSpace::Srgb, color_space)[0];
+            } else {
+                px[..3].copy_from_slice(&convert([color.r(), color.g(), color.b()], ColorSpace::Srgb, color_space));
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, QuantizeMethod};
+    use crate::dither::Dither;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+
+    const METHODS: [QuantizeMethod; 3] = [QuantizeMethod::MedianCut, QuantizeMethod::Octree, QuantizeMethod::KMeans { iterations: 10, seed: 7 }];
+
+    // four flat quadrants with 64, 32, 16 and 8 pixels and a transparent stripe
+    fn quadrants() -> FImage {
+        let colors = [[1.0, 0.0, 0.0], [0.0, 0.4, 1.0], [1.0, 1.0, 0.2], [0.0, 0.0, 0.0]];
+        let mut img = FImage::new(8, 16, PixelFormat::RGBA);
+
+        for (x, y, px) in img.enumerate_pixels_mut() {
+            let i = x + y * 8;
+            let (c, a) = match i { 0..=63 => (0, 1.0), 64..=95 => (1, 1.0), 96..=111 => (2, 0.5), 112..=119 => (3, 1.0), _ => (2, 0.0) };
+            px.copy_from_slice(&[colors[c][0], colors[c][1], colors[c][2], a]);
+        }
+
+        img
+    }
+
+    fn close(p: &Pixel, c: [f32; 3], tolerance: f32) -> bool {
+        (p.r() - c[0]).abs() <= tolerance && (p.g() - c[1]).abs() <= tolerance && (p.b() - c[2]).abs() <= tolerance
+    }
+
+    #[test]
+    fn exact_palettes() {
+        let img = quadrants();
+
+        for method in METHODS {
+            let palette = img.palette(8, method);
+            assert_eq!(palette.len(), 4, "{:?}", method);
+            let counts: Vec<usize> = palette.entries().iter().map(|e| e.count).collect();
+            assert_eq!(counts, vec![64, 32, 16, 8], "{:?}", method);
+            assert!(close(&palette.entries()[1].color, [0.0, 0.4, 1.0], 1e-3), "{:?}", method);
+
+            assert_eq!(img.palette(2, method).len(), 2, "{:?}", method);
+            assert!(img.palette(0, method).is_empty());
+        }
+    }
+
+    #[test]
+    fn remapping() {
+        let img = quadrants();
+        let palette = img.palette(4, QuantizeMethod::MedianCut);
+        let remapped = img.remap_to_palette(&palette, Dither::None);
+        assert!(remapped.data().iter().zip(img.data()).all(|(a, b)| (a - b).abs() < 1e-3));
+
+        assert_eq!(img.remap_to_palette(&Palette::new(&[]), Dither::None).data(), img.data());
+
+        let mut gray = FImage::new(32, 32, PixelFormat::Mono);
+        gray.data_mut().fill(0.5);
+        let bw = Palette::new(&[Pixel::rgb(0.0, 0.0, 0.0), Pixel::rgb(1.0, 1.0, 1.0)]);
+        assert!(gray.remap_to_palette(&bw, Dither::None).data().iter().all(|&v| v == 1.0));
+
+        for dither in [Dither::FloydSteinberg, Dither::Bayer { order: 2 }] {
+            let out = gray.remap_to_palette(&bw, dither);
+            assert!(out.data().iter().all(|&v| v.abs() < 1e-5 || (v - 1.0).abs() < 1e-5), "{:?}", dither);
+            // ordered dithering picks by Lab distance, where mid gray sits a bit closer to white
+            let mean = out.data().iter().sum::<f32>() / 1024.0;
+            assert!((mean - 0.5).abs() < 0.1, "{:?} {}", dither, mean);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use crate::error::{Error, Result};
+use crate::float_image::FImage;
+
+// Burt and Adelson's 5 tap binomial approximation of a gaussian
+const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+// source coordinates and weights of output pixel i when halving
+fn down_taps(i: usize) -> Vec<(i32, f32)> {
+    (0..5).map(|k| (2 * i as i32 + k as i32 - 2, KERNEL[k])).collect()
+}
+
+// source coordinates and weights of output pixel i when doubling, only every other tap lands on a
+// source pixel so the weights are doubled to keep their sum at 1
+fn up_taps(i: usize) -> Vec<(i32, f32)> {
+    (0..5).filter_map(|k| {
+        let j = i as i32 + k as i32 - 2;
+        if j.rem_euclid(2) == 0 { Some((j.div_euclid(2), 2.0 * KERNEL[k])) } else { None }
+    }).collect()
+}
+
+// One separable pass producing `len` pixels along the axis. Taps outside the image go through the border
+// mode, taps it skips are left out and the remaining weights renormalized, like filter_image does.
+fn filter_pass<T: Fn(usize) -> Vec<(i32, f32)>>(src: &FImage, len: usize, horizontal: bool, taps: T) -> FImage {
+    let (width, height) = if horizontal { (len, src.height()) } else { (src.width(), len) };
+    let mut out = FImage::new(width, height, src.get_pixel_format());
+    out.set_border_mode(src.border_mode().clone());
+    out.set_color_space(src.color_space());
+
+    if src.width() == 0 || src.height() == 0 {
+        return out;
+    }
+
+    let taps: Vec<Vec<(i32, f32)>> = (0..len).map(taps).collect();
+    for (x, y, px) in out.enumerate_pixels_mut() {
+        let (i, other) = if horizontal { (x, y as i32) } else { (y, x as i32) };
+        let mut used = 0.0;
+
+        for &(j, w) in &taps[i] {
+            let tap = if horizontal { src.get_pixel_checked(j, other) } else { src.get_pixel_checked(other, j) };
+            if let Some(tap) = tap {
+                used += w;
+                for (v, s) in px.iter_mut().zip(tap.slice()) {
+                    *v += w * s;
+                }
+            }
+        }
+
+        if used > 0.0 {
+            px.iter_mut().for_each(|v| *v /= used);
+        }
+    }
+
+    out
+}
+
+// elementwise a + sign * b over every channel, alpha included
+fn add_scaled(a: &FImage, b: &FImage, sign: f32) -> FImage {
+    let mut out = a.clone();
+    for (v, s) in out.data_mut().iter_mut().zip(b.data()) {
+        *v += sign * s;
+    }
+
+    out
+}
+
+// Successively blurred and halved copies of an image, level 0 is the image itself. Every channel is
+// filtered on its own, alpha included and without premultiplying.
+pub struct GaussianPyramid {
+    levels: Vec<FImage>
+}
+
+impl GaussianPyramid {
+    // at most `levels` levels and at least the image itself, fewer if the image gets down to 1x1 first
+    pub fn new(img: &FImage, levels: usize) -> GaussianPyramid {
+        let mut out = vec![img.clone()];
+
+        while out.len() < levels {
+            let last = &out[out.len() - 1];
+            if last.width() <= 1 && last.height() <= 1 {
+                break;
+            }
+
+            let next = last.pyr_down();
+            out.push(next);
+        }
+
+        GaussianPyramid { levels: out }
+    }
+
+    pub fn levels(&self) -> &[FImage] {
+        &self.levels
+    }
+
+    pub fn level(&self, level: usize) -> &FImage {
+        &self.levels[level]
+    }
+
+    // runs func(level, image) on every level, e.g. to filter at every scale
+    pub fn map<F: FnMut(usize, &FImage) -> FImage>(&self, mut func: F) -> GaussianPyramid {
+        GaussianPyramid { levels: self.levels.iter().enumerate().map(|(i, l)| func(i, l)).collect() }
+    }
+}
+
+// Band pass decomposition, every level holds the detail lost between two gaussian levels and the last
+// one is the coarsest gaussian level. Reconstruction is exact.
+pub struct LaplacianPyramid {
+    levels: Vec<FImage>
+}
+
+impl LaplacianPyramid {
+    pub fn new(img: &FImage, levels: usize) -> LaplacianPyramid {
+        LaplacianPyramid::from_gaussian(&GaussianPyramid::new(img, levels))
+    }
+
+    pub fn from_gaussian(gaussian: &GaussianPyramid) -> LaplacianPyramid {
+        let g = gaussian.levels();
+        let mut levels: Vec<FImage> = g.windows(2).map(|pair| {
+            add_scaled(&pair[0], &pair[1].pyr_up(pair[0].width(), pair[0].height()), -1.0)
+        }).collect();
+        levels.push(g[g.len() - 1].clone());
+
+        LaplacianPyramid { levels }
+    }
+
+    pub fn levels(&self) -> &[FImage] {
+        &self.levels
+    }
+
+    pub fn level(&self, level: usize) -> &FImage {
+        &self.levels[level]
+    }
+
+    pub fn map<F: FnMut(usize, &FImage) -> FImage>(&self, mut func: F) -> LaplacianPyramid {
+        LaplacianPyramid { levels: self.levels.iter().enumerate().map(|(i, l)| func(i, l)).collect() }
+    }
+
+    // collapses the pyramid back into a full size image
+    pub fn reconstruct(&self) -> FImage {
+        let mut img = self.levels[self.levels.len() - 1].clone();
+
+        for detail in self.levels.iter().rev().skip(1) {
+            img = add_scaled(detail, &img.pyr_up(detail.width(), detail.height()), 1.0);
+        }
+
+        img
+    }
+}
+
+impl FImage {
+    // blurs and halves the image, odd sizes round up
+    pub fn pyr_down(&self) -> FImage {
+        let half = filter_pass(self, self.width().div_ceil(2), true, down_taps);
+
+        filter_pass(&half, self.height().div_ceil(2), false, down_taps)
+    }
+
+    // Doubles the image and blurs it, cropped to width x height which should be about twice the current size.
+    // The inverse of pyr_down as far as the lost detail allows.
+    pub fn pyr_up(&self, width: usize, height: usize) -> FImage {
+        let wide = filter_pass(self, width, true, up_taps);
+
+        filter_pass(&wide, height, false, up_taps)
+    }
+
+    pub fn gaussian_pyramid(&self, levels: usize) -> GaussianPyramid {
+        GaussianPyramid::new(self, levels)
+    }
+
+    pub fn laplacian_pyramid(&self, levels: usize) -> LaplacianPyramid {
+        LaplacianPyramid::new(self, levels)
+    }
+
+    // Multiband blend: the laplacian levels of both images are mixed with the matching gaussian level of
+    // the mask, so coarse structure blends over a wide seam and fine detail over a narrow one. The mask's
+    // first channel is the weight of self, other gets the rest. All three need the same size and the
+    // images the same format.
+    pub fn blend_multiband(&self, other: &FImage, mask: &FImage, levels: usize) -> FImage {
+        self.try_blend_multiband(other, mask, levels).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_blend_multiband(&self, other: &FImage, mask: &FImage, levels: usize) -> Result<FImage> {
+        for img in [other, mask] {
+            if img.width() != self.width() || img.height() != self.height() {
+                return Err(Error::DimensionMismatch { expected: (self.width(), self.height()), found: (img.width(), img.height()) });
+            }
+        }
+        if other.get_pixel_format() != self.get_pixel_format() {
+            return Err(Error::UnsupportedPixelFormat { expected: self.get_pixel_format(), found: other.get_pixel_format() });
+        }
+
+        let weights = GaussianPyramid::new(&mask.channel(0), levels);
+        let a = LaplacianPyramid::new(self, levels);
+        let b = LaplacianPyramid::new(other, levels);
+        let channels = self.get_pixel_format().channel_count();
+
+        let blended = a.map(|i, la| {
+            let mut out = la.clone();
+            let lb = b.level(i);
+            let w = weights.level(i);
+
+            for ((px, pb), &t) in out.data_mut().chunks_exact_mut(channels).zip(lb.data().chunks_exact(channels)).zip(w.data()) {
+                for (v, &vb) in px.iter_mut().zip(pb) {
+                    *v = *v * t + vb * (1.0 - t);
+                }
+            }
+
+            out
+        });
+
+        Ok(blended.reconstruct())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GaussianPyramid;
+    use crate::error::Error;
+    use crate::float_image::{BorderMode, FImage, PixelFormat};
+
+    fn noise(w: usize, h: usize, format: PixelFormat) -> FImage {
+        let mut img = FImage::new(w, h, format);
+        let mut state = 0x9e3779b9u32;
+        for v in img.data_mut() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *v = (state % 1000) as f32 / 1000.0;
+        }
+
+        img
+    }
+
+    #[test]
+    fn levels_halve_and_keep_flat_images_flat() {
+        let mut flat = FImage::new(13, 6, PixelFormat::RGB);
+        flat.data_mut().fill(0.3);
+        flat.set_border_mode(BorderMode::Skip);
+
+        let pyramid = GaussianPyramid::new(&flat, 10);
+        let sizes: Vec<(usize, usize)> = pyramid.levels().iter().map(|l| (l.width(), l.height())).collect();
+        assert_eq!(sizes, vec![(13, 6), (7, 3), (4, 2), (2, 1), (1, 1)]);
+        assert!(pyramid.levels().iter().all(|l| l.data().iter().all(|v| (v - 0.3).abs() < 1e-6)));
+        assert!(matches!(pyramid.level(2).border_mode(), BorderMode::Skip));
+
+        assert_eq!(flat.pyr_up(26, 12).data().len(), 26 * 12 * 3);
+        assert!(flat.pyr_up(26, 12).data().iter().all(|v| (v - 0.3).abs() < 1e-6));
+    }
+
+    #[test]
+    fn laplacian_reconstruction_is_exact() {
+        for (w, h) in [(16, 16), (21, 10), (1, 7)] {
+            for mode in [BorderMode::Mirror, BorderMode::Clamp, BorderMode::Wrap] {
+                let mut img = noise(w, h, PixelFormat::RGBA);
+                img.set_border_mode(mode);
+
+                let back = img.laplacian_pyramid(5).reconstruct();
+                assert!(back.data().iter().zip(img.data()).all(|(a, b)| (a - b).abs() < 1e-5), "{}x{}", w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn multiband_blend() {
+        let mut a = noise(16, 8, PixelFormat::Mono);
+        a.set_border_mode(BorderMode::Clamp);
+        let b = a.map(|v| v + 2.0);
+
+        // a uniform mask gives a plain mix
+        let mut mask = FImage::new(16, 8, PixelFormat::Mono);
+        mask.data_mut().fill(0.25);
+        let out = a.blend_multiband(&b, &mask, 4);
+        assert!(out.data().iter().zip(a.data()).all(|(o, v)| (o - (v + 1.5)).abs() < 1e-5));
+
+        // and a hard edge stays put far from the seam, everything is clamped so it doesn't wrap around
+        mask.set_border_mode(BorderMode::Clamp);
+        for (x, _, px) in mask.enumerate_pixels_mut() {
+            px[0] = if x < 8 { 1.0 } else { 0.0 };
+        }
+        let out = a.blend_multiband(&b, &mask, 2);
+        assert!((out.get_pixel(0, 3).r() - a.get_pixel(0, 3).r()).abs() < 0.05);
+        assert!((out.get_pixel(15, 3).r() - b.get_pixel(15, 3).r()).abs() < 0.05);
+
+        assert!(matches!(a.try_blend_multiband(&b.to_format(PixelFormat::RGB), &mask, 2), Err(Error::UnsupportedPixelFormat { .. })));
+        assert!(matches!(a.try_blend_multiband(&b, &FImage::new(2, 2, PixelFormat::Mono), 2), Err(Error::DimensionMismatch { .. })));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::compositing::{premultiply, unpremultiply};
+use crate::float_image::{FImage, Pixel, PixelFormat};
+use crate::image_view::GenericFImage;
+use crate::transform::ResizeFilter;
+
+// Reads images at fractional coordinates, pixel centers sit at i + 0.5. Taps go through the
+// image's border mode, taps it skips are left out and the remaining weights renormalized.
+// RGBA taps are weighted with premultiplied alpha so transparent pixels don't bleed color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampler {
+    filter: ResizeFilter
+}
+
+fn tap<I: GenericFImage + ?Sized>(img: &I, x: i32, y: i32) -> Option<[f32; 4]> {
+    let p = img.get_pixel_checked(x, y)?;
+    let mut c = [0.0f32; 4];
+    c[..p.slice().len()].copy_from_slice(p.slice());
+
+    Some(if p.format() == PixelFormat::RGBA { premultiply(c) } else { c })
+}
+
+fn finish(acc: [f32; 4], total: f32, format: PixelFormat) -> [f32; 4] {
+    if total == 0.0 {
+        return [0.0; 4];
+    }
+
+    let c = acc.map(|v| v / total);
+
+    if format == PixelFormat::RGBA { unpremultiply(c) } else { c }
+}
+
+impl Sampler {
+    pub fn new(filter: ResizeFilter) -> Sampler {
+        Sampler { filter }
+    }
+
+    pub fn filter(&self) -> ResizeFilter {
+        self.filter
+    }
+
+    pub fn sample<I: GenericFImage + ?Sized>(&self, img: &I, x: f32, y: f32) -> Pixel<'static> {
+        Pixel::from_array(self.sample_array(img, x, y), img.get_pixel_format())
+    }
+
+    pub(crate) fn sample_array<I: GenericFImage + ?Sized>(&self, img: &I, x: f32, y: f32) -> [f32; 4] {
+        let format = img.get_pixel_format();
+
+        if !x.is_finite() || !y.is_finite() {
+            return [0.0; 4];
+        }
+
+        if self.filter == ResizeFilter::Nearest {
+            return match tap(img, x.floor() as i32, y.floor() as i32) {
+                Some(c) => finish(c, 1.0, format),
+                None => [0.0; 4]
+            };
+        }
+
+        let support = self.filter.support();
+        let (fx, fy) = (x - 0.5, y - 0.5);
+        let mut acc = [0.0f32; 4];
+        let mut total = 0.0;
+
+        for ty in (fy - support).ceil() as i32..=(fy + support).floor() as i32 {
+            let wy = self.filter.weight(ty as f32 - fy);
+            if wy == 0.0 {
+                continue;
+            }
+
+            for tx in (fx - support).ceil() as i32..=(fx + support).floor() as i32 {
+                let w = wy * self.filter.weight(tx as f32 - fx);
+                if w == 0.0 {
+                    continue;
+                }
+
+                if let Some(c) = tap(img, tx, ty) {
+                    for (a, v) in acc.iter_mut().zip(c) {
+                        *a += w * v;
+                    }
+                    total += w;
+                }
+            }
+        }
+
+        finish(acc, total, format)
+    }
+}
+
+// average over the rectangle [x0, x1) x [y0, y1), pixels on the edge count by how much of them is covered
+pub fn sample_area<I: GenericFImage + ?Sized>(img: &I, x0: f32, y0: f32, x1: f32, y1: f32) -> Pixel<'static> {
+    let format = img.get_pixel_format();
+    let (x0, x1) = (x0.min(x1), x0.max(x1));
+    let (y0, y1) = (y0.min(y1), y0.max(y1));
+
+    if !(x0.is_finite() && x1.is_finite() && y0.is_finite() && y1.is_finite()) {
+        return Pixel::from_array([0.0; 4], format);
+    }
+
+    // degenerate rectangles fall back to the pixel they sit in
+    if x1 - x0 == 0.0 || y1 - y0 == 0.0 {
+        return Sampler::new(ResizeFilter::Nearest).sample(img, x0, y0);
+    }
+
+    let mut acc = [0.0f32; 4];
+    let mut total = 0.0;
+
+    for ty in y0.floor() as i32..y1.ceil() as i32 {
+        let wy = (y1.min(ty as f32 + 1.0) - y0.max(ty as f32)).max(0.0);
+
+        for tx in x0.floor() as i32..x1.ceil() as i32 {
+            let w = wy * (x1.min(tx as f32 + 1.0) - x0.max(tx as f32)).max(0.0);
+            if w == 0.0 {
+                continue;
+            }
+
+            if let Some(c) = tap(img, tx, ty) {
+                for (a, v) in acc.iter_mut().zip(c) {
+                    *a += w * v;
+                }
+                total += w;
+            }
+        }
+    }
+
+    Pixel::from_array(finish(acc, total, format), format)
+}
+
+// chain of successively halved copies of an image, for sampling with a large footprint without aliasing
+pub struct MipMap {
+    levels: Vec<FImage>
+}
+
+impl MipMap {
+    pub fn new(img: &FImage) -> MipMap {
+        let mut levels = vec![img.clone()];
+
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width() <= 1 && last.height() <= 1 {
+                break;
+            }
+
+            let next = last.resize(last.width().div_ceil(2), last.height().div_ceil(2), ResizeFilter::Bilinear);
+            levels.push(next);
+        }
+
+        MipMap { levels }
+    }
+
+    pub fn levels(&self) -> &[FImage] {
+        &self.levels
+    }
+
+    pub fn level(&self, level: usize) -> &FImage {
+        &self.levels[level]
+    }
+
+    // Samples at level 0 coordinates, footprint is the size in level 0 pixels that one sample covers.
+    // Blends between the two nearest levels.
+    pub fn sample(&self, sampler: &Sampler, x: f32, y: f32, footprint: f32) -> Pixel<'static> {
+        let base = &self.levels[0];
+        let level = footprint.max(1.0).log2().min((self.levels.len() - 1) as f32);
+        let l0 = level.floor() as usize;
+        let l1 = (l0 + 1).min(self.levels.len() - 1);
+        let t = level - l0 as f32;
+
+        let at = |l: usize| {
+            let img = &self.levels[l];
+            let sx = x * img.width() as f32 / base.width().max(1) as f32;
+            let sy = y * img.height() as f32 / base.height().max(1) as f32;
+
+            sampler.sample_array(img, sx, sy)
+        };
+
+        let a = at(l0);
+        let c = if t > 0.0 && l1 != l0 { at(l1) } else { a };
+
+        let mixed = [0, 1, 2, 3].map(|i| a[i] + (c[i] - a[i]) * t);
+
+        Pixel::from_array(mixed, base.get_pixel_format())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_area, MipMap, Sampler};
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+    use crate::transform::ResizeFilter;
+
+    fn ramp() -> FImage {
+        // value x + 10 y
+        let mut img = FImage::new(4, 3, PixelFormat::Mono);
+        for (x, y, px) in img.enumerate_pixels_mut() {
+            px[0] = (x + 10 * y) as f32;
+        }
+
+        img
+    }
+
+    #[test]
+    fn pixel_centers_return_the_pixel() {
+        let img = ramp();
+
+        for filter in [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::Bicubic, ResizeFilter::Lanczos3] {
+            let s = Sampler::new(filter);
+            assert!((s.sample(&img, 2.5, 1.5).r() - 12.0).abs() < 1e-4, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn bilinear_interpolates_between_centers() {
+        let mut img = ramp();
+        img.set_border_mode(BorderMode::Clamp);
+        let s = Sampler::new(ResizeFilter::Bilinear);
+
+        assert!((s.sample(&img, 2.0, 1.5).r() - 11.5).abs() < 1e-5);
+        assert!((s.sample(&img, 1.75, 1.0).r() - 6.25).abs() < 1e-5);
+        // clamped outside the image
+        assert!((s.sample(&img, -3.0, 0.5).r() - 0.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn skipped_taps_renormalize() {
+        let mut img = FImage::new(2, 1, PixelFormat::Mono);
+        img.data_mut().fill(0.5);
+        img.set_border_mode(BorderMode::Skip);
+
+        assert!((Sampler::new(ResizeFilter::Bicubic).sample(&img, 0.2, 0.5).r() - 0.5).abs() < 1e-5);
+        assert_eq!(Sampler::new(ResizeFilter::Bilinear).sample(&img, -5.0, 0.5).r(), 0.0);
+        assert_eq!(Sampler::new(ResizeFilter::Bilinear).sample(&img, f32::NAN, 0.5).r(), 0.0);
+    }
+
+    #[test]
+    fn transparent_taps_dont_bleed_color() {
+        let mut img = FImage::new(2, 1, PixelFormat::RGBA);
+        img.set_pixel(0, 0, Pixel::rgba(1.0, 0.0, 0.0, 0.0));
+        img.set_pixel(1, 0, Pixel::rgba(0.0, 0.0, 1.0, 1.0));
+        let p = Sampler::new(ResizeFilter::Bilinear).sample(&img, 1.0, 0.5);
+
+        assert!((p.a() - 0.5).abs() < 1e-5);
+        assert!(p.r().abs() < 1e-5 && (p.b() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn area_and_mipmap_average() {
+        let img = ramp();
+        assert!((sample_area(&img, 0.0, 0.0, 2.0, 2.0).r() - 5.5).abs() < 1e-5);
+        assert!((sample_area(&img, 0.5, 0.0, 1.5, 1.0).r() - 0.5).abs() < 1e-5);
+
+        let mut checker = FImage::new(64, 64, PixelFormat::Mono);
+        for (x, y, px) in checker.enumerate_pixels_mut() {
+            px[0] = ((x + y) % 2) as f32;
+        }
+        let mip = MipMap::new(&checker);
+        assert_eq!(mip.levels().len(), 7);
+        assert_eq!((mip.level(6).width(), mip.level(6).height()), (1, 1));
+        // a wide footprint sees the average, not whichever checker it lands on
+        let p = mip.sample(&Sampler::new(ResizeFilter::Bilinear), 20.5, 31.5, 16.0);
+        assert!((p.r() - 0.5).abs() < 0.05);
+    }
+}
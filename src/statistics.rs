@@ -0,0 +1,240 @@
+use crate::error::{Error, Result};
+use crate::float_image::FImage;
+use crate::image_view::Rect;
+
+// which pixels statistics are gathered from
+#[derive(Clone, Copy)]
+pub enum Selection<'a> {
+    All,
+    Region(Rect),
+    // pixels where the mask's first channel is 0.5 or more, the mask has to match the image size
+    Mask(&'a FImage)
+}
+
+// Statistics of one channel, NaNs are skipped. With nothing selected count is 0, the values are NaN
+// and the locations are (0, 0).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    pub count: usize,
+    pub sum: f32,
+    pub mean: f32,
+    // population variance
+    pub variance: f32,
+    pub min: f32,
+    pub max: f32,
+    pub min_location: (usize, usize),
+    pub max_location: (usize, usize)
+}
+
+impl ChannelStats {
+    pub fn std_dev(&self) -> f32 {
+        self.variance.sqrt()
+    }
+}
+
+// running mean and squared deviations with Welford's update, which doesn't cancel the way
+// sum_sq / n - mean^2 does when the mean is large compared to the spread
+struct Accumulator {
+    count: usize,
+    sum: f64,
+    mean: f64,
+    m2: f64,
+    min: (f32, (usize, usize)),
+    max: (f32, (usize, usize))
+}
+
+impl Accumulator {
+    fn new() -> Accumulator {
+        Accumulator { count: 0, sum: 0.0, mean: 0.0, m2: 0.0, min: (f32::INFINITY, (0, 0)), max: (f32::NEG_INFINITY, (0, 0)) }
+    }
+
+    fn add(&mut self, v: f32, x: usize, y: usize) {
+        if v.is_nan() {
+            return;
+        }
+
+        self.count += 1;
+        self.sum += v as f64;
+        let delta = v as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (v as f64 - self.mean);
+        if v < self.min.0 {
+            self.min = (v, (x, y));
+        }
+        if v > self.max.0 {
+            self.max = (v, (x, y));
+        }
+    }
+
+    fn finish(&self) -> ChannelStats {
+        if self.count == 0 {
+            return ChannelStats { count: 0, sum: 0.0, mean: f32::NAN, variance: f32::NAN, min: f32::NAN, max: f32::NAN,
+                                  min_location: (0, 0), max_location: (0, 0) };
+        }
+
+        let variance = self.m2 / self.count as f64;
+
+        ChannelStats {
+            count: self.count,
+            sum: self.sum as f32,
+            mean: self.mean as f32,
+            variance: variance as f32,
+            min: self.min.0,
+            max: self.max.0,
+            min_location: self.min.1,
+            max_location: self.max.1
+        }
+    }
+}
+
+// linearly interpolated quantile (q in [0, 1]) of values without NaNs, reorders values
+pub(crate) fn quantile(values: &mut [f32], q: f32) -> f32 {
+    if values.is_empty() {
+        return f32::NAN;
+    }
+
+    let rank = q.clamp(0.0, 1.0) * (values.len() - 1) as f32;
+    let lo = rank.floor() as usize;
+
+    let (_, &mut lo_value, upper) = values.select_nth_unstable_by(lo, f32::total_cmp);
+    let hi_value = if upper.is_empty() { lo_value } else { upper.iter().copied().fold(f32::INFINITY, f32::min) };
+
+    lo_value + (rank - lo as f32) * (hi_value - lo_value)
+}
+
+impl FImage {
+    fn check_selection(&self, selection: &Selection) -> Result<()> {
+        match selection {
+            Selection::All => Ok(()),
+            Selection::Region(rect) if !rect.fits_in(self.width(), self.height()) => {
+                Err(Error::InvalidRect { rect: *rect, width: self.width(), height: self.height() })
+            },
+            Selection::Region(_) => Ok(()),
+            Selection::Mask(mask) if mask.width() != self.width() || mask.height() != self.height() => {
+                Err(Error::DimensionMismatch { expected: (self.width(), self.height()), found: (mask.width(), mask.height()) })
+            },
+            Selection::Mask(_) => Ok(())
+        }
+    }
+
+    // calls func with the coordinates and channels of every selected pixel, the selection must be valid
+    fn for_each_selected<F: FnMut(usize, usize, &[f32])>(&self, selection: &Selection, mut func: F) {
+        let rect = match selection {
+            Selection::Region(rect) => *rect,
+            _ => Rect::new(0, 0, self.width(), self.height())
+        };
+
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                if let Selection::Mask(mask) = selection {
+                    if mask.pixel_slice(x, y)[0] < 0.5 {
+                        continue;
+                    }
+                }
+
+                func(x, y, self.pixel_slice(x, y));
+            }
+        }
+    }
+
+    // one entry per channel, alpha included
+    pub fn statistics(&self) -> Vec<ChannelStats> {
+        self.statistics_in(Selection::All)
+    }
+
+    pub fn statistics_in(&self, selection: Selection) -> Vec<ChannelStats> {
+        self.try_statistics_in(selection).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_statistics_in(&self, selection: Selection) -> Result<Vec<ChannelStats>> {
+        self.check_selection(&selection)?;
+
+        let mut acc: Vec<Accumulator> = (0..self.get_pixel_format().channel_count()).map(|_| Accumulator::new()).collect();
+        self.for_each_selected(&selection, |x, y, px| {
+            for (a, &v) in acc.iter_mut().zip(px) {
+                a.add(v, x, y);
+            }
+        });
+
+        Ok(acc.iter().map(Accumulator::finish).collect())
+    }
+
+    // quantiles (q in [0, 1], interpolated between values) of every channel, indexed [channel][q]
+    pub fn quantiles(&self, qs: &[f32], selection: Selection) -> Vec<Vec<f32>> {
+        self.try_quantiles(qs, selection).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_quantiles(&self, qs: &[f32], selection: Selection) -> Result<Vec<Vec<f32>>> {
+        self.check_selection(&selection)?;
+
+        let mut values: Vec<Vec<f32>> = vec![Vec::new(); self.get_pixel_format().channel_count()];
+        self.for_each_selected(&selection, |_, _, px| {
+            for (list, &v) in values.iter_mut().zip(px) {
+                if !v.is_nan() {
+                    list.push(v);
+                }
+            }
+        });
+
+        Ok(values.iter_mut().map(|list| qs.iter().map(|&q| quantile(list, q)).collect()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Selection;
+    use crate::error::Error;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+    use crate::image_view::Rect;
+
+    fn ramp() -> FImage {
+        // value x + 10 y
+        let mut img = FImage::new(4, 3, PixelFormat::Mono);
+        for (x, y, px) in img.enumerate_pixels_mut() {
+            px[0] = (x + 10 * y) as f32;
+        }
+
+        img
+    }
+
+    #[test]
+    fn channel_statistics() {
+        let mut img = ramp();
+        img.data_mut()[5] = f32::NAN;
+        let s = img.statistics()[0];
+
+        assert_eq!((s.count, s.sum, s.min, s.max), (11, 127.0, 0.0, 23.0));
+        assert_eq!((s.min_location, s.max_location), ((0, 0), (3, 2)));
+        assert!((s.mean - 127.0 / 11.0).abs() < 1e-5);
+
+        let empty = FImage::new(0, 0, PixelFormat::RGB).statistics();
+        assert!(empty.len() == 3 && empty[0].count == 0 && empty[0].mean.is_nan());
+    }
+
+    #[test]
+    fn variance_is_stable_with_a_large_mean() {
+        // sum_sq / n - mean^2 comes out negative for these
+        let mut img = FImage::new(64, 64, PixelFormat::Mono);
+        img.data_mut().iter_mut().enumerate().for_each(|(i, v)| *v = 1e7 + (i % 2) as f32);
+        let s = img.statistics()[0];
+
+        assert!((s.variance - 0.25).abs() < 1e-6, "{}", s.variance);
+    }
+
+    #[test]
+    fn selections() {
+        let img = ramp();
+
+        let region = img.statistics_in(Selection::Region(Rect::new(1, 1, 2, 2)))[0];
+        assert_eq!((region.count, region.sum), (4, 66.0));
+
+        let mut mask = FImage::new(4, 3, PixelFormat::Mono);
+        mask.set_pixel(3, 0, Pixel::mono(1.0));
+        mask.set_pixel(0, 2, Pixel::mono(0.5));
+        assert_eq!(img.quantiles(&[0.0, 0.5, 1.0], Selection::Mask(&mask)), vec![vec![3.0, 11.5, 20.0]]);
+
+        assert!(matches!(img.try_statistics_in(Selection::Region(Rect::new(3, 0, 2, 1))), Err(Error::InvalidRect { .. })));
+        assert!(matches!(img.try_quantiles(&[0.5], Selection::Mask(&FImage::new(2, 2, PixelFormat::Mono))), Err(Error::DimensionMismatch { .. })));
+        assert!(img.quantiles(&[0.5], Selection::Mask(&FImage::new(4, 3, PixelFormat::Mono)))[0][0].is_nan());
+    }
+}
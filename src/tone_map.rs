@@ -0,0 +1,194 @@
+use image::{GenericImage, Primitive};
+
+use crate::color_space::{linear_to_srgb, ColorSpace};
+use crate::error::Result;
+use crate::float_image::{FImage, PixelFormat};
+
+// curves from scene values in [0, inf) to display values in [0, 1], negative values map to 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    Clip,
+    Reinhard,
+    // white is the smallest value that maps to 1
+    ReinhardExtended { white: f32 },
+    // Uncharted 2 filmic curve
+    Hable,
+    // Narkowicz's fit of the ACES reference rendering transform
+    Aces,
+    // exposure in stops, followed by a 1 / gamma power curve
+    Exposure { exposure: f32, gamma: f32 }
+}
+
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+impl ToneMap {
+    pub fn apply(&self, c: f32) -> f32 {
+        let x = c.max(0.0);
+
+        let v = match *self {
+            ToneMap::Clip => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::ReinhardExtended { white } => x * (1.0 + x / (white * white)) / (1.0 + x),
+            ToneMap::Hable => hable_partial(2.0 * x) / hable_partial(11.2),
+            ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMap::Exposure { exposure, gamma } => (x * exposure.exp2()).powf(1.0 / gamma)
+        };
+
+        v.clamp(0.0, 1.0)
+    }
+}
+
+// Pulls colors with negative components back towards the achromatic axis. Components further than
+// threshold (in [0, 1)) from the largest component are compressed smoothly so none go below zero.
+pub fn compress_gamut(c: [f32; 3], threshold: f32) -> [f32; 3] {
+    let ach = c[0].max(c[1]).max(c[2]);
+    if ach <= 0.0 {
+        return c;
+    }
+
+    c.map(|v| {
+        let d = (ach - v) / ach;
+        if d <= threshold {
+            return v;
+        }
+
+        let over = d - threshold;
+        let compressed = threshold + over / (1.0 + over / (1.0 - threshold));
+
+        ach - compressed * ach
+    })
+}
+
+// how out of range values are brought into [0, 1] for display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    pub tone_map: ToneMap,
+    // None leaves negative components to be clipped by the tone map
+    pub gamut_threshold: Option<f32>
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform { tone_map: ToneMap::Clip, gamut_threshold: None }
+    }
+}
+
+impl DisplayTransform {
+    pub fn new(tone_map: ToneMap, gamut_threshold: Option<f32>) -> DisplayTransform {
+        DisplayTransform { tone_map, gamut_threshold }
+    }
+}
+
+impl FImage {
+    // maps the color channels, alpha is left alone
+    pub fn tone_map(&mut self, op: ToneMap) {
+        self.map_in_place(|v| op.apply(v));
+    }
+
+    // mono images have no gamut to compress and are left unchanged
+    pub fn compress_gamut(&mut self, threshold: f32) {
+        if self.get_pixel_format() == PixelFormat::Mono {
+            return;
+        }
+
+        let channels = self.get_pixel_format().channel_count();
+        for px in self.data_mut().chunks_exact_mut(channels) {
+            let c = compress_gamut([px[0], px[1], px[2]], threshold);
+            px[..3].copy_from_slice(&c);
+        }
+    }
+
+    // Display referred sRGB copy of the image. sRGB images are mapped as they are, anything else is
+    // tone mapped in linear light and encoded afterwards.
+    pub fn to_display(&self, transform: &DisplayTransform) -> FImage {
+        let linear = self.color_space() != ColorSpace::Srgb;
+        let mut out = if linear { self.to_color_space(ColorSpace::LinearSrgb) } else { self.clone() };
+
+        if let Some(threshold) = transform.gamut_threshold {
+            out.compress_gamut(threshold);
+        }
+        out.tone_map(transform.tone_map);
+
+        if linear {
+            out.map_in_place(linear_to_srgb);
+            out.set_color_space(ColorSpace::Srgb);
+        }
+
+        if out.get_pixel_format() == PixelFormat::RGBA {
+            for px in out.data_mut().chunks_exact_mut(4) {
+                px[3] = px[3].clamp(0.0, 1.0);
+            }
+        }
+
+        out
+    }
+
+    pub fn copy_to_image_buffer_with<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&self, image: &mut I, transform: &DisplayTransform) {
+        self.try_copy_to_image_buffer_with(image, transform).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_copy_to_image_buffer_with<SP: Primitive, P: image::Pixel<Subpixel = SP>, I: GenericImage<Pixel = P>>(&self, image: &mut I, transform: &DisplayTransform) -> Result<()> {
+        self.to_display(transform).try_copy_to_image_buffer(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress_gamut, DisplayTransform, ToneMap};
+    use crate::color_space::ColorSpace;
+    use crate::float_image::{FImage, Pixel, PixelFormat};
+
+    const CURVES: [ToneMap; 6] = [ToneMap::Clip, ToneMap::Reinhard, ToneMap::ReinhardExtended { white: 4.0 }, ToneMap::Hable, ToneMap::Aces, ToneMap::Exposure { exposure: 1.0, gamma: 2.2 }];
+
+    #[test]
+    fn curves_are_monotonic_and_in_range() {
+        for op in CURVES {
+            assert!(op.apply(-1.0).abs() < 1e-6 && op.apply(-1.0) == op.apply(0.0), "{:?}", op);
+
+            let mut last = 0.0;
+            for i in 0..400 {
+                let v = op.apply(i as f32 * 0.05);
+                assert!((0.0..=1.0).contains(&v) && v >= last - 1e-6, "{:?} at {}", op, i);
+                last = v;
+            }
+        }
+
+        assert!((ToneMap::Reinhard.apply(1.0) - 0.5).abs() < 1e-6);
+        assert!((ToneMap::ReinhardExtended { white: 4.0 }.apply(4.0) - 1.0).abs() < 1e-6);
+        assert!((ToneMap::Hable.apply(5.6) - 1.0).abs() < 1e-5);
+        assert!((ToneMap::Exposure { exposure: 1.0, gamma: 1.0 }.apply(0.25) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gamut_compression() {
+        // colors within the threshold are untouched
+        assert_eq!(compress_gamut([1.0, 0.9, 0.5], 0.6), [1.0, 0.9, 0.5]);
+
+        let c = compress_gamut([1.0, 0.5, -0.5], 0.2);
+        assert_eq!(c[0], 1.0);
+        // pulled towards the largest component, the negative one only as far as zero
+        assert!(c[1] > 0.5 && c[1] < 1.0 && c[2] >= 0.0 && c[2] < 0.5);
+        assert_eq!(compress_gamut([-1.0, -2.0, -0.5], 0.2), [-1.0, -2.0, -0.5]);
+    }
+
+    #[test]
+    fn display_copies() {
+        let mut img = FImage::new(2, 1, PixelFormat::RGBA);
+        img.set_pixel(0, 0, Pixel::rgba(4.0, 1.0, -0.5, 1.5));
+        img.set_pixel(1, 0, Pixel::rgba(0.2, 0.2, 0.2, -0.5));
+
+        let out = img.to_display(&DisplayTransform::default());
+        assert_eq!(out.data(), &[1.0, 1.0, 0.0, 1.0, 0.2, 0.2, 0.2, 0.0]);
+
+        // linear images are encoded to srgb after the curve
+        img.set_color_space(ColorSpace::LinearSrgb);
+        let out = img.to_display(&DisplayTransform::new(ToneMap::Reinhard, Some(0.2)));
+        assert_eq!(out.color_space(), ColorSpace::Srgb);
+        assert!(out.data().iter().all(|v| (0.0..=1.0).contains(v)));
+        assert!((out.get_pixel(1, 0).r() - 0.445).abs() < 0.01);
+    }
+}
@@ -0,0 +1,363 @@
+use std::f32::consts::PI;
+
+use crate::compositing::unpremultiply;
+use crate::error::{Error, Result};
+use crate::float_image::{FImage, PixelFormat};
+use crate::sampler::Sampler;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Mitchell,
+    Lanczos3
+}
+
+// Mitchell-Netravali family of cubics
+fn cubic(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let t = PI * x;
+        t.sin() / t
+    }
+}
+
+impl ResizeFilter {
+    // radius of the kernel in source pixels
+    pub fn support(&self) -> f32 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Bicubic | ResizeFilter::Mitchell => 2.0,
+            ResizeFilter::Lanczos3 => 3.0
+        }
+    }
+
+    pub fn weight(&self, x: f32) -> f32 {
+        match self {
+            ResizeFilter::Nearest => if (-0.5..0.5).contains(&x) { 1.0 } else { 0.0 },
+            ResizeFilter::Bilinear => (1.0 - x.abs()).max(0.0),
+            ResizeFilter::Bicubic => cubic(x, 0.0, 0.5), // Catmull-Rom
+            ResizeFilter::Mitchell => cubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            ResizeFilter::Lanczos3 => if x.abs() < 3.0 { sinc(x) * sinc(x / 3.0) } else { 0.0 }
+        }
+    }
+}
+
+// source pixels (clamped to the image) and normalized weights for every output coordinate
+fn resize_weights(src_len: usize, dst_len: usize, filter: ResizeFilter) -> Vec<Vec<(usize, f32)>> {
+    let scale = dst_len as f32 / src_len as f32;
+    // widen the kernel when shrinking so every source pixel contributes, nearest stays a point sample
+    let filter_scale = if filter == ResizeFilter::Nearest { 1.0 } else { (1.0 / scale).max(1.0) };
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len).map(|i| {
+        let center = (i as f32 + 0.5) / scale;
+        let start = (center - support - 0.5).floor() as i32;
+        let end = (center + support + 0.5).ceil() as i32;
+
+        let mut taps: Vec<(usize, f32)> = (start..=end).filter_map(|j| {
+            let w = filter.weight((j as f32 + 0.5 - center) / filter_scale);
+            if w == 0.0 { None } else { Some((j.clamp(0, src_len as i32 - 1) as usize, w)) }
+        }).collect();
+
+        let total: f32 = taps.iter().map(|t| t.1).sum();
+        if total != 0.0 {
+            for t in taps.iter_mut() {
+                t.1 /= total;
+            }
+        }
+
+        taps
+    }).collect()
+}
+
+fn invert_affine(m: [[f32; 3]; 2]) -> Result<[[f32; 3]; 2]> {
+    let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    if det == 0.0 || !det.is_finite() {
+        return Err(Error::SingularTransform);
+    }
+
+    let a = m[1][1] / det;
+    let b = -m[0][1] / det;
+    let d = -m[1][0] / det;
+    let e = m[0][0] / det;
+
+    Ok([[a, b, -(a * m[0][2] + b * m[1][2])],
+        [d, e, -(d * m[0][2] + e * m[1][2])]])
+}
+
+fn invert_homography(m: [[f32; 3]; 3]) -> Result<[[f32; 3]; 3]> {
+    let cof = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+
+    let det = m[0][0] * cof(1, 2, 1, 2) - m[0][1] * cof(1, 2, 0, 2) + m[0][2] * cof(1, 2, 0, 1);
+    if det == 0.0 || !det.is_finite() {
+        return Err(Error::SingularTransform);
+    }
+
+    Ok([[cof(1, 2, 1, 2) / det, -cof(0, 2, 1, 2) / det, cof(0, 1, 1, 2) / det],
+        [-cof(1, 2, 0, 2) / det, cof(0, 2, 0, 2) / det, -cof(0, 1, 0, 2) / det],
+        [cof(1, 2, 0, 1) / det, -cof(0, 2, 0, 1) / det, cof(0, 1, 0, 1) / det]])
+}
+
+impl FImage {
+    // copy with premultiplied alpha, so resampling doesn't pull color out of transparent pixels
+    fn premultiplied_copy(&self) -> FImage {
+        let mut tmp = self.clone();
+        tmp.premultiply();
+
+        tmp
+    }
+
+    fn unpremultiply_if_rgba(&mut self) {
+        if self.get_pixel_format() == PixelFormat::RGBA {
+            for px in self.data_mut().chunks_exact_mut(4) {
+                px.copy_from_slice(&unpremultiply([px[0], px[1], px[2], px[3]]));
+            }
+        }
+    }
+
+    pub fn resize(&self, width: usize, height: usize, filter: ResizeFilter) -> FImage {
+        let channels = self.get_pixel_format().channel_count();
+        let src = self.premultiplied_copy();
+
+        let mut out = FImage::new(width, height, self.get_pixel_format());
+        out.set_border_mode(self.border_mode().clone());
+        out.set_color_space(self.color_space());
+
+        if self.width() == 0 || self.height() == 0 {
+            return out;
+        }
+
+        // horizontal pass into a width x src height buffer, then vertical
+        let xw = resize_weights(self.width(), width, filter);
+        let mut tmp = vec![0.0f32; width * self.height() * channels];
+        for (y, row) in tmp.chunks_exact_mut((width * channels).max(1)).enumerate() {
+            for (x, px) in row.chunks_exact_mut(channels).enumerate() {
+                for &(sx, w) in &xw[x] {
+                    for (c, v) in px.iter_mut().enumerate() {
+                        *v += w * src.pixel_slice(sx, y)[c];
+                    }
+                }
+            }
+        }
+
+        let yw = resize_weights(self.height(), height, filter);
+        for (y, row) in out.rows_mut().enumerate() {
+            for (x, px) in row.chunks_exact_mut(channels).enumerate() {
+                for &(sy, w) in &yw[y] {
+                    let offset = (sy * width + x) * channels;
+                    for (c, v) in px.iter_mut().enumerate() {
+                        *v += w * tmp[offset + c];
+                    }
+                }
+            }
+        }
+
+        out.unpremultiply_if_rgba();
+
+        out
+    }
+
+    fn remap<F: Fn(usize, usize) -> (usize, usize)>(&self, width: usize, height: usize, src_coords: F) -> FImage {
+        let mut out = FImage::new(width, height, self.get_pixel_format());
+        out.set_border_mode(self.border_mode().clone());
+        out.set_color_space(self.color_space());
+
+        for (x, y, px) in out.enumerate_pixels_mut() {
+            let (sx, sy) = src_coords(x, y);
+            px.copy_from_slice(self.pixel_slice(sx, sy));
+        }
+
+        out
+    }
+
+    // clockwise rotations by multiples of 90 degrees
+    pub fn rotate90(&self) -> FImage {
+        let h = self.height();
+        self.remap(self.height(), self.width(), |x, y| (y, h - 1 - x))
+    }
+
+    pub fn rotate180(&self) -> FImage {
+        let (w, h) = (self.width(), self.height());
+        self.remap(w, h, |x, y| (w - 1 - x, h - 1 - y))
+    }
+
+    pub fn rotate270(&self) -> FImage {
+        let w = self.width();
+        self.remap(self.height(), self.width(), |x, y| (w - 1 - y, x))
+    }
+
+    pub fn flip_horizontal(&self) -> FImage {
+        let w = self.width();
+        self.remap(w, self.height(), |x, y| (w - 1 - x, y))
+    }
+
+    pub fn flip_vertical(&self) -> FImage {
+        let h = self.height();
+        self.remap(self.width(), h, |x, y| (x, h - 1 - y))
+    }
+
+    // inverse maps every output pixel center into the source image
+    fn warp<F: Fn(f32, f32) -> (f32, f32)>(&self, width: usize, height: usize, filter: ResizeFilter, src_coords: F) -> FImage {
+        let channels = self.get_pixel_format().channel_count();
+        let sampler = Sampler::new(filter);
+
+        let mut out = FImage::new(width, height, self.get_pixel_format());
+        out.set_border_mode(self.border_mode().clone());
+        out.set_color_space(self.color_space());
+
+        for (x, y, px) in out.enumerate_pixels_mut() {
+            let (sx, sy) = src_coords(x as f32 + 0.5, y as f32 + 0.5);
+            px.copy_from_slice(&sampler.sample_array(self, sx, sy)[..channels]);
+        }
+
+        out
+    }
+
+    // matrix maps source to destination: x' = m[0][0] x + m[0][1] y + m[0][2], y' = m[1][0] x + m[1][1] y + m[1][2]
+    pub fn warp_affine(&self, matrix: [[f32; 3]; 2], width: usize, height: usize, filter: ResizeFilter) -> FImage {
+        self.try_warp_affine(matrix, width, height, filter).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_warp_affine(&self, matrix: [[f32; 3]; 2], width: usize, height: usize, filter: ResizeFilter) -> Result<FImage> {
+        let inv = invert_affine(matrix)?;
+
+        Ok(self.warp(width, height, filter, |x, y| {
+            (inv[0][0] * x + inv[0][1] * y + inv[0][2], inv[1][0] * x + inv[1][1] * y + inv[1][2])
+        }))
+    }
+
+    // homography maps source to destination in homogeneous coordinates
+    pub fn warp_perspective(&self, homography: [[f32; 3]; 3], width: usize, height: usize, filter: ResizeFilter) -> FImage {
+        self.try_warp_perspective(homography, width, height, filter).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_warp_perspective(&self, homography: [[f32; 3]; 3], width: usize, height: usize, filter: ResizeFilter) -> Result<FImage> {
+        let inv = invert_homography(homography)?;
+
+        Ok(self.warp(width, height, filter, |x, y| {
+            let w = inv[2][0] * x + inv[2][1] * y + inv[2][2];
+            if w == 0.0 {
+                return (f32::NAN, f32::NAN);
+            }
+
+            ((inv[0][0] * x + inv[0][1] * y + inv[0][2]) / w, (inv[1][0] * x + inv[1][1] * y + inv[1][2]) / w)
+        }))
+    }
+
+    // clockwise rotation about the image center, keeping the image size. Uncovered corners are
+    // filled according to the border mode, use Constant or Skip to leave them transparent.
+    pub fn rotate(&self, angle: f32, filter: ResizeFilter) -> FImage {
+        let (cx, cy) = (self.width() as f32 / 2.0, self.height() as f32 / 2.0);
+        let (sin, cos) = angle.sin_cos();
+
+        let matrix = [[cos, -sin, cx - cos * cx + sin * cy],
+                      [sin, cos, cy - sin * cx - cos * cy]];
+
+        self.warp_affine(matrix, self.width(), self.height(), filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResizeFilter;
+    use crate::error::Error;
+    use crate::float_image::{BorderMode, FImage, Pixel, PixelFormat};
+
+    const FILTERS: [ResizeFilter; 5] = [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::Bicubic, ResizeFilter::Mitchell, ResizeFilter::Lanczos3];
+
+    fn numbered(width: usize, height: usize, format: PixelFormat) -> FImage {
+        let mut img = FImage::new(width, height, format);
+        for (i, v) in img.data_mut().iter_mut().enumerate() {
+            *v = i as f32 / 64.0;
+        }
+
+        img
+    }
+
+    fn close(a: &FImage, b: &FImage, tolerance: f32) -> bool {
+        (a.width(), a.height()) == (b.width(), b.height()) && a.data().iter().zip(b.data()).all(|(x, y)| (x - y).abs() <= tolerance)
+    }
+
+    #[test]
+    fn right_angle_rotations_and_flips() {
+        let img = numbered(3, 2, PixelFormat::Mono);
+        let r = img.rotate90();
+
+        assert_eq!((r.width(), r.height()), (2, 3));
+        assert_eq!(r.data(), [3.0, 0.0, 4.0, 1.0, 5.0, 2.0].map(|v| v / 64.0));
+        assert_eq!(r.rotate90().rotate90().rotate90().data(), img.data());
+        assert_eq!(r.rotate270().data(), img.data());
+        assert_eq!(img.rotate180().data(), img.flip_horizontal().flip_vertical().data());
+        assert_eq!(img.flip_horizontal().data(), [2.0, 1.0, 0.0, 5.0, 4.0, 3.0].map(|v| v / 64.0));
+    }
+
+    #[test]
+    fn same_size_resize_keeps_interpolating_filters_exact() {
+        let img = numbered(7, 5, PixelFormat::RGB);
+
+        for filter in [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::Bicubic, ResizeFilter::Lanczos3] {
+            assert!(close(&img.resize(7, 5, filter), &img, 1e-5), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn resizing_a_flat_image_keeps_it_flat() {
+        let mut img = FImage::new(9, 6, PixelFormat::RGB);
+        img.data_mut().fill(0.3);
+
+        for filter in FILTERS {
+            for (w, h) in [(20, 13), (4, 3), (1, 1)] {
+                let out = img.resize(w, h, filter);
+                assert!(out.data().iter().all(|v| (v - 0.3).abs() < 1e-5), "{:?} to {}x{}", filter, w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_dont_bleed_color() {
+        let mut img = FImage::new(2, 1, PixelFormat::RGBA);
+        img.set_pixel(0, 0, Pixel::rgba(1.0, 0.0, 0.0, 0.0));
+        img.set_pixel(1, 0, Pixel::rgba(0.0, 0.0, 1.0, 1.0));
+
+        for filter in FILTERS {
+            for (x, _, px) in img.resize(8, 1, filter).enumerate_pixels_mut() {
+                if px[3] > 1e-3 {
+                    assert!(px[0].abs() < 1e-4, "{:?} pulled red into pixel {}", filter, x);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn warps() {
+        let mut img = numbered(6, 4, PixelFormat::RGB);
+        img.set_border_mode(BorderMode::Clamp);
+
+        assert!(close(&img.warp_affine([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]], 6, 4, ResizeFilter::Bilinear), &img, 1e-5));
+        assert!(close(&img.warp_perspective([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], 6, 4, ResizeFilter::Bicubic), &img, 1e-5));
+        assert!(close(&img.rotate(0.0, ResizeFilter::Lanczos3), &img, 1e-5));
+
+        // a translation by whole pixels is a shift
+        let shifted = img.warp_affine([[1.0, 0.0, 2.0], [0.0, 1.0, 1.0]], 6, 4, ResizeFilter::Bilinear);
+        assert_eq!(shifted.get_pixel(3, 2).slice(), img.get_pixel(1, 1).slice());
+
+        assert!(matches!(img.try_warp_affine([[1.0, 2.0, 0.0], [2.0, 4.0, 0.0]], 6, 4, ResizeFilter::Bilinear), Err(Error::SingularTransform)));
+        assert!(matches!(img.try_warp_perspective([[0.0; 3]; 3], 6, 4, ResizeFilter::Bilinear), Err(Error::SingularTransform)));
+    }
+}